@@ -11,6 +11,8 @@ type ContractTokenAmount = TokenAmountU64;
 pub const OPERATOR_OF_ENTRYPOINT_NAME: &str = "operatorOf";
 pub const BALANCE_OF_ENTRYPOINT_NAME: &str = "balanceOf";
 pub const TRANSFER_ENTRYPOINT_NAME: &str = "transfer";
+pub const ON_RECEIVING_CIS2_ENTRYPOINT_NAME: &str = "onReceivingCIS2";
+pub const SUPPORTS_ENTRYPOINT_NAME: &str = "supports";
 
 type ContractBalanceOfQueryParams = BalanceOfQueryParams<ContractTokenId>;
 type ContractBalanceOfQueryResponse = BalanceOfQueryResponse<ContractTokenAmount>;
@@ -18,26 +20,539 @@ type TransferParameter = TransferParams<ContractTokenId, ContractTokenAmount>;
 
 pub const SECOND_PER_YEAR: &u64 = &(365 * 24 * 60 * 60);
 
+/// Pause flag blocking `stake`.
+pub const PAUSE_STAKE: u8 = 1;
+/// Pause flag blocking `unstake`.
+pub const PAUSE_UNSTAKE: u8 = 1 << 1;
+/// Pause flag blocking `claim` and `claimResidual`.
+pub const PAUSE_CLAIM: u8 = 1 << 2;
+
+/// Number of halvings after which the reward rate is treated as zero.
+const MAX_HALVINGS: u64 = 64;
+
+/// Upper bound on the number of no-accrual windows accepted at init.
+pub const MAX_NO_ACCRUAL_WINDOWS: usize = 32;
+
+/// Number of most recent stake idempotency keys remembered per account.
+pub const MAX_IDEMPOTENCY_KEYS: usize = 16;
+
+/// Milliseconds ahead that `projectedMaturityValue` projects positions
+/// without a defined maturity.
+pub const DEFAULT_PROJECTION_HORIZON_MILLIS: u64 = 365 * 24 * 60 * 60 * 1000;
+
+/// Maximum number of positions `viewStakeAgeBuckets` scans per call.
+pub const MAX_BUCKET_SCAN: usize = 500;
+
+/// Maximum number of positions `forceSettleAll` settles per call.
+pub const MAX_SETTLE_BATCH: usize = 200;
+
+/// Maximum number of stakers `listStakers` returns per call.
+pub const MAX_LIST_STAKERS: u64 = 100;
+
+/// Maximum number of entries accepted by `batchStake`.
+pub const MAX_BATCH_STAKE: usize = 100;
+
+/// Upper bounds in milliseconds of the `viewStakeAgeBuckets` buckets, below
+/// one day, one week and one month. Older positions fall in a last bucket.
+const STAKE_AGE_BUCKET_BOUNDS: [u64; 3] =
+    [24 * 60 * 60 * 1000, 7 * 24 * 60 * 60 * 1000, 30 * 24 * 60 * 60 * 1000];
+
+/// Fixed-point scale of the pool-rate reward accumulator.
+const REWARD_PER_TOKEN_PRECISION: u128 = 1_000_000_000_000;
+
+/// Operation currently in flight for a position. Anything other than
+/// `Active` means an unstake or claim is waiting on an external call.
+#[derive(Clone, Copy, Serialize, SchemaType, PartialEq, Eq)]
+enum PositionStatus {
+    Active,
+    Unstaking,
+    Claiming,
+}
+
 #[derive(Clone, Serialize, SchemaType)]
 struct StakeState {
     amount: u64,
-    staked_start_at: u64
+    /// Time in milliseconds at which the position was opened. Top-ups, claims
+    /// and settlement leave it alone. Like every timestamp in the contract it
+    /// is in milliseconds; durations configured in seconds are converted
+    /// before comparing.
+    staked_start_at: u64,
+    status: PositionStatus,
+    /// Account that supplied the principal when staked by someone else via
+    /// `stakeFor`; principal is returned there on unstake.
+    funder: Option<AccountAddress>,
+    /// Reward settled before the last amount change and not yet paid out;
+    /// accrual since `last_claimed_at` comes on top of it.
+    accrued: u64,
+    /// Time in milliseconds before which the position cannot be unstaked.
+    lock_until: u64,
+    /// Signed adjustment applied to the reward when it is paid out. A
+    /// penalty is negative and never reduces the payout below zero; what it
+    /// does not offset carries over to later payouts.
+    adjustment: i64,
+    /// Pool-rate accumulator value up to which the position's reward has
+    /// been settled.
+    reward_per_token_paid: u128,
+    /// Time in milliseconds of the last deposit into the position.
+    deposited_at: u64,
+    /// Principal requested for unstaking and awaiting the cooldown. It no
+    /// longer counts towards `amount` and does not accrue reward.
+    unbonding_amount: u64,
+    /// Time in milliseconds of the last unstake request.
+    unbonding_at: u64,
+    /// Time in milliseconds from which the position accrues on top of
+    /// `accrued`: its opening, or its last claim or settlement, whichever
+    /// came last. Every payout of reward settles the position first.
+    last_claimed_at: u64,
+    /// Time in milliseconds of the last deposit into the position or action
+    /// by its owner on it. Administrative settlement leaves it alone.
+    last_interaction_at: u64
 }
 
 impl StakeState {
     fn empty() -> Self {
         StakeState {
             amount: 0u64,
-            staked_start_at: 0u64
+            staked_start_at: 0u64,
+            status: PositionStatus::Active,
+            funder: None,
+            accrued: 0u64,
+            lock_until: 0u64,
+            adjustment: 0i64,
+            reward_per_token_paid: 0u128,
+            deposited_at: 0u64,
+            unbonding_amount: 0u64,
+            unbonding_at: 0u64,
+            last_claimed_at: 0u64,
+            last_interaction_at: 0u64
         }
     }
 }
 
-#[derive(Serial, DeserialWithState, StateClone)]
+/// Cliff-then-linear schedule over which claimed reward is released.
+#[derive(Clone, Copy, Serialize, SchemaType)]
+struct VestingConfig {
+    /// Seconds after a claim before any of it is released.
+    cliff_seconds:    u64,
+    /// Seconds after a claim at which all of it is released. Release is
+    /// linear from the claim, so the cliff releases its share at once.
+    duration_seconds: u64,
+}
+
+/// Reward from a single claim being released under the vesting schedule.
+#[derive(Serialize, SchemaType)]
+struct VestingEntry {
+    total:    u64,
+    released: u64,
+    /// Time in milliseconds of the claim.
+    start:    u64,
+}
+
+/// Time-boxed period outside of which stakes do not accrue reward.
+#[derive(Clone, Copy, Serialize, SchemaType)]
+struct Season {
+    /// Time in milliseconds at which accrual starts.
+    start: u64,
+    /// Time in milliseconds at which accrual stops.
+    end:   u64,
+}
+
+/// Reward rate and season that were in effect until `until`, when an admin
+/// replaced them. Reward accrued before a change keeps being computed under
+/// the terms in effect at the time, so no position has to be settled.
+#[derive(Serialize, SchemaType)]
+struct PastTerms {
+    /// Time in milliseconds at which the terms were replaced.
+    until:    u64,
+    rate_bps: u16,
+    season:   Option<Season>,
+}
+
+/// Reward rate reduction for accounts that repeatedly unstake soon after
+/// depositing.
+#[derive(Clone, Copy, Serialize, SchemaType)]
+struct EarlyExitDecay {
+    /// Seconds after a deposit within which unstaking counts as an early
+    /// exit. Unstaking later forgives all recorded early exits.
+    window_seconds:   u64,
+    /// Reduction of the account's reward rate per recorded early exit, in
+    /// basis points.
+    penalty_bps:      u16,
+    /// Seconds after the last early exit at which one recorded early exit is
+    /// forgiven.
+    recovery_seconds: u64,
+}
+
+/// Early exits recorded for an account under `EarlyExitDecay`.
+#[derive(Clone, Copy, Serialize, SchemaType)]
+struct EarlyExitRecord {
+    count:        u32,
+    /// Time in milliseconds of the last early exit.
+    last_exit_at: u64,
+}
+
+/// Schedule splitting the reward paid out by an unstake into installments.
+#[derive(Clone, Copy, Serialize, SchemaType)]
+struct InstallmentConfig {
+    /// Number of installments the reward is split into.
+    count:            u32,
+    /// Seconds between the unstake and the first installment, and between
+    /// subsequent installments.
+    interval_seconds: u64,
+}
+
+/// Part of an unstake's reward that becomes claimable at `release_at`.
+#[derive(Serialize, SchemaType)]
+struct Installment {
+    amount:     u64,
+    release_at: u64,
+}
+
+/// Reward left unpaid by an unstake, claimable until `expires_at`.
+#[derive(Serialize, SchemaType)]
+struct ResidualReward {
+    amount:     u64,
+    expires_at: u64,
+}
+
+/// A pending transfer of a position to `to`, which `to` must accept before
+/// `expires_at`.
+#[derive(Serialize, SchemaType)]
+struct PositionOffer {
+    to:         AccountAddress,
+    expires_at: u64,
+}
+
+#[derive(Serial, DeserialWithState)]
 #[concordium(state_parameter = "S")]
 struct State<S> {
-    stake:        StateMap<AccountAddress, StakeState, S>,
-    total_staked: u64
+    stake:                 StateMap<AccountAddress, StakeState, S>,
+    total_staked:          u64,
+    /// Number of accounts with principal staked in their `stake` position or
+    /// in lots.
+    staker_count:          u64,
+    /// Residual reward owed to accounts that fully unstaked.
+    pending_after_unstake: StateMap<AccountAddress, ResidualReward, S>,
+    /// How long in milliseconds a residual reward stays claimable.
+    residual_claim_window: u64,
+    /// Sorted, non-overlapping `(start, end)` intervals in milliseconds during
+    /// which no reward accrues.
+    no_accrual_windows:    Vec<(u64, u64)>,
+    /// Upper bound on the reward paid out by a single transaction.
+    max_reward_per_tx:     Option<u64>,
+    /// Number of decimals of the staked token, for display by clients.
+    token_decimals:        u8,
+    /// Which events are logged.
+    event_level:           EventLevel,
+    /// Whether staked principal is transferred into the contract.
+    custody:               bool,
+    /// Principal held by the contract in custody mode. Principal and reward
+    /// share a single token, so this and `reward_pool` form the liability
+    /// ledger that keeps principal out of reward payouts.
+    held_principal:        u64,
+    /// Reward tokens held by the contract, funded with `fundRewardPool`.
+    /// Reward payouts are only ever debited from here.
+    reward_pool:           u64,
+    /// Time in milliseconds at which the program starts. No reward accrues
+    /// for time before it.
+    program_start:         u64,
+    /// Seconds between reward halvings, if the rate halves over time.
+    halving_interval:      Option<u64>,
+    /// Account allowed to call administrative entrypoints.
+    admin:                 AccountAddress,
+    /// Account receiving swept dust.
+    treasury:              AccountAddress,
+    /// Cumulative reward lost to rounding down payouts, in units of
+    /// `1 / reward_denominator()` tokens. Rounding in pool-rate mode, at
+    /// halvings and of early-exit reductions is not included.
+    rounding_loss:         u128,
+    /// Seconds over which a stake earns `reward_rate_bps` of its amount.
+    reward_period:         u64,
+    /// Pending position transfers, keyed by the current owner.
+    position_offers:       StateMap<AccountAddress, PositionOffer, S>,
+    /// Bitfield of `PAUSE_*` flags for the entrypoints currently paused.
+    pause_flags:           u8,
+    /// Pay out pending reward when an existing stake is topped up.
+    auto_claim_on_top_up:  bool,
+    /// Cap on the claims processed within a single block. `None` disables
+    /// the throttle.
+    max_claims_per_block:  Option<u32>,
+    /// Slot time of the block the last claim was processed in, and the number
+    /// of claims processed in that block.
+    block_claims:          (u64, u32),
+    /// Discrete lots staked with `stakeNewLot`, each with its own amount,
+    /// start and lock, keyed by owner and then lot id. Lots count towards
+    /// `total_staked` but are independent of the owner's `stake` position.
+    lots:                  StateMap<AccountAddress, StateMap<u64, StakeState, S>, S>,
+    /// Id assigned to the next lot.
+    next_lot_id:           u64,
+    /// Schedule releasing reward over time, whichever entrypoint pays it out.
+    /// `None` pays reward out immediately.
+    vesting:               Option<VestingConfig>,
+    /// Claimed reward not yet fully released, per claim.
+    vesting_entries:       StateMap<AccountAddress, Vec<VestingEntry>, S>,
+    /// Schedule for paying out unstake reward in installments. `None` pays
+    /// it out in a lump sum.
+    installments:          Option<InstallmentConfig>,
+    /// Installments of unstake reward not yet claimed.
+    pending_installments:  StateMap<AccountAddress, Vec<Installment>, S>,
+    /// Fee withheld from unstaked principal, in basis points.
+    unstake_fee_bps:       u16,
+    /// Where the unstake fee goes.
+    fee_destination:       FeeDestination,
+    /// Most recent idempotency keys of stakes per account, oldest first.
+    stake_keys:            StateMap<AccountAddress, Vec<u64>, S>,
+    /// How payouts are cut when the reward pool falls short.
+    shortfall_policy:      ShortfallPolicy,
+    /// Current staking season. `None` accrues without a time limit. Earlier
+    /// seasons are kept in `past_terms`.
+    season:                Option<Season>,
+    /// Bonus credited to a referrer once per account they referred, on its
+    /// first referred stake, in basis points of the staked amount.
+    referral_bonus_bps:    u16,
+    /// Referrer of each referred account, fixed by its first referred stake.
+    referrers:             StateMap<AccountAddress, AccountAddress, S>,
+    /// Referral bonus credited to each referrer and not yet claimed.
+    referral_rewards:      StateMap<AccountAddress, u64, S>,
+    /// Positions staking native CCD with `stakeCcd`, in microCCD. They earn
+    /// the staked token as reward, at the per-stake rate on their value in
+    /// the staked token under `tokens_per_ccd`.
+    ccd_stake:             StateMap<AccountAddress, StakeState, S>,
+    /// Total microCCD staked, kept apart from the token principal.
+    total_ccd_staked:      u64,
+    /// Seconds new stakes are locked for. Each position records its own lock
+    /// when staked, so changing this does not affect existing positions.
+    min_lock_seconds:      u64,
+    /// Seconds without interaction after which a position's unclaimed reward
+    /// can be reclaimed. `None` disables reclaiming.
+    reclaim_after:         Option<u64>,
+    /// Tokens emitted per second and split across token positions and lots
+    /// by their share of `total_staked`. `None` accrues each position at the
+    /// per-stake rate instead. CCD positions always use the per-stake rate.
+    reward_per_second:     Option<u64>,
+    /// Reward earned per `reward_period`, in basis points of the staked
+    /// amount. Earlier rates are kept in `past_terms`.
+    reward_rate_bps:       u16,
+    /// Whether positions earn any reward. Without it the contract only
+    /// accounts for staked principal and never transfers reward.
+    rewards_enabled:       bool,
+    /// Reward per staked token emitted so far under `reward_per_second`,
+    /// scaled by `REWARD_PER_TOKEN_PRECISION`.
+    reward_per_token:      u128,
+    /// Time in milliseconds up to which `reward_per_token` is accumulated.
+    pool_updated_at:       u64,
+    /// Reward rate reduction for serial early exits. `None` disables it.
+    early_exit_decay:      Option<EarlyExitDecay>,
+    /// Early exits recorded per account under `early_exit_decay`.
+    early_exits:           StateMap<AccountAddress, EarlyExitRecord, S>,
+    /// Tag payout transfers with the payout kind and account in their
+    /// additional data.
+    payout_memo:           bool,
+    /// The staked token's contract. Token contract addresses passed to
+    /// entrypoints must match it.
+    token_contract:        ContractAddress,
+    /// Seconds between requesting an unstake and withdrawing the principal.
+    /// With a cooldown, `unstake` is replaced by `requestUnstake` and
+    /// `withdraw`.
+    cooldown_seconds:      u64,
+    /// Set while an entrypoint that calls out to the token contract is
+    /// running, so that the token contract cannot call back into it.
+    locked:                bool,
+    /// Most principal a single account may hold across its stake position and
+    /// lots. `None` is uncapped.
+    max_stake_per_account: Option<u64>,
+    /// Smallest deposit opening a stake position.
+    min_stake_amount:      u64,
+    /// Reward rate tiers as `(min_seconds, bps)`, ascending by `min_seconds`.
+    /// Empty uses `reward_rate_bps` for every position.
+    apr_tiers:             Vec<(u64, u16)>,
+    /// Smallest units of the staked token one CCD is valued at, for reward
+    /// and limits on CCD positions. `None` disables `stakeCcd`.
+    tokens_per_ccd:        Option<u64>,
+    /// Terms replaced by `setRewardRate` and `startNewSeason`, oldest first,
+    /// keyed by their position in that order.
+    past_terms:            StateMap<u32, PastTerms, S>,
+    /// Number of entries in `past_terms`.
+    past_terms_count:      u32,
+    /// Reward settled or reserved and not yet paid out or forfeited: the
+    /// `accrued` ledgers of all positions and residual, vesting, installment
+    /// and referral reward. Reward accrued since a position was last settled
+    /// is not included.
+    total_liabilities:     u128,
+    /// Reward accrued since positions were last settled, in units of
+    /// `1 / reward_denominator` tokens. Estimated from the totals staked at the
+    /// reward rate, without tiers or early-exit reductions.
+    unsettled_accrual:     u128,
+    /// Time in milliseconds up to which `unsettled_accrual` is accumulated.
+    unsettled_updated_at:  u64,
+}
+
+/// Trades off indexability against energy by gating which events are logged.
+#[derive(Clone, Copy, Serialize, SchemaType, PartialEq, Eq)]
+enum EventLevel {
+    /// No events are logged.
+    Off,
+    /// Only events describing payouts are logged.
+    Financial,
+    /// Every event is logged.
+    All,
+}
+
+/// How payouts are cut when the reward pool cannot cover all pending reward.
+#[derive(Clone, Copy, Serialize, SchemaType, PartialEq, Eq)]
+enum ShortfallPolicy {
+    /// Payouts are covered in full while the pool lasts.
+    FirstComeFirstServed,
+    /// Every payout gets the fraction of the owed reward that the pool
+    /// covers of all settled reward, and the rest is forfeited.
+    ProRata,
+}
+
+/// Where the fee charged on unstaked principal goes.
+#[derive(Clone, Copy, Serialize, SchemaType, PartialEq, Eq)]
+pub enum FeeDestination {
+    /// Transferred out to the treasury account.
+    Treasury,
+    /// Added to the reward pool, benefitting the remaining stakers.
+    RewardPool,
+}
+
+#[derive(Serialize, SchemaType)]
+pub enum StakingEvent {
+    /// A payout was capped at `max_reward_per_tx`.
+    RewardClamped {
+        owner:    AccountAddress,
+        computed: u64,
+        paid:     u64,
+    },
+    /// Part of the accrued reward was added to the staked principal.
+    Compounded {
+        owner:  AccountAddress,
+        amount: u64,
+    },
+    /// Accrued reward was paid out while the principal stays staked.
+    Claimed {
+        owner:  AccountAddress,
+        reward: u64,
+    },
+    /// A fee was withheld from principal returned by an unstake.
+    UnstakeFeeCharged {
+        owner:       AccountAddress,
+        amount:      u64,
+        destination: FeeDestination,
+    },
+    /// Reward left unclaimed by an inactive position was returned to the
+    /// reward pool.
+    RewardReclaimed {
+        owner:  AccountAddress,
+        amount: u64,
+    },
+    /// Tokens were added to a position.
+    Staked {
+        owner:  AccountAddress,
+        amount: u64,
+    },
+    /// `amount` of principal was withdrawn from a position, paying out
+    /// `reward`.
+    Unstaked {
+        owner:  AccountAddress,
+        amount: u64,
+        reward: u64,
+    },
+    /// `amount` microCCD were added to a CCD position.
+    CcdStaked {
+        owner:  AccountAddress,
+        amount: u64,
+    },
+    /// A CCD position was closed, returning `amount` microCCD and paying out
+    /// `reward` in the staked token.
+    CcdUnstaked {
+        owner:  AccountAddress,
+        amount: u64,
+        reward: u64,
+    },
+    /// `amount` of reward was left unpaid by an exit because the reward pool
+    /// did not cover it. It stays owed to the owner, as residual reward or
+    /// accrued on their stake position.
+    RewardShortfall {
+        owner:  AccountAddress,
+        amount: u64,
+    },
+}
+
+/// Format version of logged events.
+///
+/// Events are logged as a format byte followed by the `StakingEvent`
+/// encoding. The format byte is `EVENT_VERSION_MARKER | version`. Unversioned
+/// events logged before versioning (v0) start directly with the variant tag,
+/// which never has the marker bit set, so decoders can tell them apart. New
+/// variants are only ever appended, so older payloads stay decodable.
+pub const EVENT_FORMAT_VERSION: u8 = 1;
+
+/// Bit set on the format byte of versioned events.
+const EVENT_VERSION_MARKER: u8 = 0x80;
+
+/// A `StakingEvent` as logged, prefixed with its format byte.
+struct VersionedEvent<'a>(&'a StakingEvent);
+
+impl Serial for VersionedEvent<'_> {
+    fn serial<W: Write>(&self, out: &mut W) -> Result<(), W::Err> {
+        (EVENT_VERSION_MARKER | EVENT_FORMAT_VERSION).serial(out)?;
+        self.0.serial(out)
+    }
+}
+
+impl StakingEvent {
+    /// Decodes a logged event of any format version up to the current one.
+    pub fn deserial_versioned<R: Read>(source: &mut R) -> ParseResult<Self> {
+        let first = u8::deserial(source)?;
+        if first & EVENT_VERSION_MARKER == 0 {
+            // v0: the byte read is already the variant tag.
+            return StakingEvent::deserial(&mut Prefixed {
+                first: Some(first),
+                rest:  source,
+            });
+        }
+        ensure!(first & !EVENT_VERSION_MARKER <= EVENT_FORMAT_VERSION, ParseError::default());
+        StakingEvent::deserial(source)
+    }
+
+    /// Whether the event describes a payout, and so is logged at
+    /// `EventLevel::Financial`.
+    fn is_financial(&self) -> bool {
+        match self {
+            StakingEvent::RewardClamped {
+                ..
+            }
+            | StakingEvent::Claimed {
+                ..
+            }
+            | StakingEvent::UnstakeFeeCharged {
+                ..
+            }
+            | StakingEvent::Unstaked {
+                ..
+            }
+            | StakingEvent::CcdUnstaked {
+                ..
+            } => true,
+            StakingEvent::Compounded {
+                ..
+            }
+            | StakingEvent::RewardReclaimed {
+                ..
+            }
+            | StakingEvent::Staked {
+                ..
+            }
+            | StakingEvent::CcdStaked {
+                ..
+            }
+            | StakingEvent::RewardShortfall {
+                ..
+            } => false,
+        }
+    }
 }
 
 #[derive(Serialize, Debug, PartialEq, Eq, Reject, SchemaType)]
@@ -50,12 +565,57 @@ enum CustomContractError {
     InvokeContractError,
     NoBalance,
     NotOperator,
+    InvalidNoAccrualWindows,
+    NoResidualReward,
+    ResidualExpired,
+    PositionBusy,
+    NotCustody,
+    InsufficientRewardPool,
+    InvalidHalvingInterval,
+    ZeroTimeBasis,
+    NoPositionOffer,
+    OfferExpired,
+    RecipientHasPosition,
+    ContractPaused,
+    SelfInvocation,
+    CompoundExceedsReward,
+    FunderMismatch,
+    ClaimThrottled,
+    StillLocked,
+    InvalidVesting,
+    NothingVested,
+    StateInconsistent,
+    InvalidInstallments,
+    NoInstallmentDue,
+    InvalidFee,
+    InvalidSeason,
+    PositionsOutstanding,
+    SelfReferral,
+    InvalidReferralBonus,
+    NoReferralReward,
+    AmountOverflow,
+    NotInactive,
+    InvalidEarlyExitDecay,
+    WrongTokenContract,
+    CoolingDown,
+    UnsupportedTokenContract,
+    LimitTooLarge,
+    Reentrancy,
+    StakeCapExceeded,
+    BelowMinimumStake,
+    InvalidTiers,
+    LogFull,
+    LogMalformed,
+    InvalidUnstakeAmount,
+    CcdStakingDisabled,
+    DuplicateOwnerInBatch,
 }
-#[derive(Serialize, Debug, PartialEq, Eq, Reject)]
+#[derive(Serialize, Debug, PartialEq, Eq, Reject, SchemaType)]
 pub enum Cis2ClientError {
     InvokeContractError,
     ParseParams,
     ParseResult,
+    InvalidResponse,
 }
 
 type ContractError = Cis2Error<CustomContractError>;
@@ -67,16 +627,89 @@ impl<T> From<CallContractError<T>> for CustomContractError {
     fn from(_cce: CallContractError<T>) -> Self { Self::InvokeContractError }
 }
 
+impl From<LogError> for CustomContractError {
+    fn from(le: LogError) -> Self {
+        match le {
+            LogError::Full => Self::LogFull,
+            LogError::Malformed => Self::LogMalformed,
+        }
+    }
+}
+
 impl From<CustomContractError> for ContractError {
     fn from(c: CustomContractError) -> Self { Cis2Error::Custom(c) }
 }
 
 impl<S: HasStateApi> State<S> {
     /// Creates a new state with no tokens.
-    fn empty(state_builder: &mut StateBuilder<S>) -> Self {
+    fn empty(
+        state_builder: &mut StateBuilder<S>,
+        params: InitParams,
+        admin: AccountAddress,
+        program_start: u64,
+    ) -> Self {
         State {
-            stake:        state_builder.new_map(),
-            total_staked: 0u64,
+            stake:                 state_builder.new_map(),
+            total_staked:          0u64,
+            staker_count:          0u64,
+            pending_after_unstake: state_builder.new_map(),
+            residual_claim_window: params.residual_claim_window,
+            no_accrual_windows:    params.no_accrual_windows,
+            max_reward_per_tx:     params.max_reward_per_tx,
+            token_decimals:        params.token_decimals,
+            event_level:           params.event_level,
+            custody:               params.custody,
+            held_principal:        0u64,
+            reward_pool:           0u64,
+            program_start,
+            halving_interval:      params.halving_interval_seconds,
+            admin,
+            treasury:              params.treasury,
+            rounding_loss:         0u128,
+            reward_period:         params.reward_period_seconds,
+            position_offers:       state_builder.new_map(),
+            pause_flags:           0u8,
+            auto_claim_on_top_up:  params.auto_claim_on_top_up,
+            max_claims_per_block:  params.max_claims_per_block,
+            block_claims:          (0u64, 0u32),
+            lots:                  state_builder.new_map(),
+            next_lot_id:           0u64,
+            vesting:               params.vesting,
+            vesting_entries:       state_builder.new_map(),
+            installments:          params.installments,
+            pending_installments:  state_builder.new_map(),
+            unstake_fee_bps:       params.unstake_fee_bps,
+            fee_destination:       params.fee_destination,
+            stake_keys:            state_builder.new_map(),
+            shortfall_policy:      params.shortfall_policy,
+            season:                params.season,
+            referral_bonus_bps:    params.referral_bonus_bps,
+            referrers:             state_builder.new_map(),
+            referral_rewards:      state_builder.new_map(),
+            ccd_stake:             state_builder.new_map(),
+            total_ccd_staked:      0u64,
+            min_lock_seconds:      params.min_lock_seconds,
+            reclaim_after:         params.reclaim_after_seconds,
+            reward_per_second:     params.reward_per_second,
+            reward_rate_bps:       params.reward_rate_per_year_bps,
+            rewards_enabled:       params.rewards_enabled,
+            reward_per_token:      0u128,
+            pool_updated_at:       program_start,
+            early_exit_decay:      params.early_exit_decay,
+            early_exits:           state_builder.new_map(),
+            payout_memo:           params.payout_memo,
+            token_contract:        params.token_contract,
+            cooldown_seconds:      params.cooldown_seconds,
+            locked:                false,
+            max_stake_per_account: params.max_stake_per_account,
+            min_stake_amount:      params.min_stake_amount,
+            apr_tiers:             params.apr_tiers,
+            tokens_per_ccd:        params.tokens_per_ccd,
+            past_terms:            state_builder.new_map(),
+            past_terms_count:      0u32,
+            total_liabilities:     0u128,
+            unsettled_accrual:     0u128,
+            unsettled_updated_at:  program_start,
         }
     }
 
@@ -85,321 +718,6937 @@ impl<S: HasStateApi> State<S> {
         owner: &AccountAddress,
         amount: u64,
         staked_time: u64,
-        state_builder: &mut StateBuilder<S>,
+        funder: Option<AccountAddress>,
     ) -> ContractResult<()> {
+        let owner_state =
+            self.stake.entry(*owner).or_insert_with(StakeState::empty);
+        ensure!(owner_state.status == PositionStatus::Active, CustomContractError::PositionBusy.into());
+        // Principal of one position is returned to a single account, so it
+        // cannot mix funds from different funders.
+        ensure!(
+            owner_state.amount == 0 || owner_state.funder == funder,
+            CustomContractError::FunderMismatch.into()
+        );
+        drop(owner_state);
+        self.settle(owner, staked_time)?;
+        let lock_until = self.lock_until(staked_time);
         let mut owner_state =
-            self.stake.entry(*owner).or_insert_with(|| StakeState::empty());
-        owner_state.amount = amount;
-        owner_state.staked_start_at = staked_time;
-        self.total_staked += amount;
-        Ok(())
+            self.stake.entry(*owner).or_insert_with(StakeState::empty);
+        let amount_before = owner_state.amount;
+        // Only the deposit opening a position must meet the minimum, so that
+        // existing stakers can top up by any amount.
+        ensure!(
+            amount_before > 0 || amount >= self.min_stake_amount,
+            CustomContractError::BelowMinimumStake.into()
+        );
+        owner_state.funder = funder;
+        // A repeated stake adds to the position. The reward accrued so far
+        // was settled above, so the whole amount accrues from `staked_time`.
+        if amount_before == 0 {
+            owner_state.staked_start_at = staked_time;
+        }
+        owner_state.last_claimed_at = staked_time;
+        owner_state.deposited_at = staked_time;
+        owner_state.last_interaction_at = staked_time;
+        // The added amount is locked under the current terms, without
+        // shortening a longer lock the position already has.
+        owner_state.lock_until = owner_state.lock_until.max(lock_until);
+        drop(owner_state);
+        self.add_principal(owner, amount)
     }
 
-    fn remove_token(
-        &mut self,
-        owner: &AccountAddress,
-        state_builder: &mut StateBuilder<S>,
-    ) -> ContractResult<()> {
-        let mut owner_state =
-            self.stake.entry(*owner).or_insert_with(|| StakeState::empty());
-        self.total_staked -= owner_state.amount;
-        owner_state.amount = 0u64;
-        owner_state.staked_start_at = 0u64;
-        Ok(())
+    /// Adds `amount` to the principal of the owner's position, which must
+    /// have been settled first, and updates every aggregate over positions
+    /// accordingly.
+    fn add_principal(&mut self, owner: &AccountAddress, amount: u64) -> ContractResult<()> {
+        self.ensure_within_cap(owner, amount)?;
+        let mut owner_state = self.stake.get_mut(owner).ok_or(CustomContractError::TokenNotFound)?;
+        let amount_before = owner_state.amount;
+        let amount_after =
+            amount_before.checked_add(amount).ok_or(CustomContractError::AmountOverflow)?;
+        owner_state.amount = amount_after;
+        drop(owner_state);
+        let total_before = self.total_staked;
+        self.total_staked =
+            self.total_staked.checked_add(amount).ok_or(CustomContractError::AmountOverflow)?;
+        if amount_before == 0 && amount_after > 0 && self.lot_principal(owner) == 0 {
+            self.staker_count += 1;
+        }
+        ensure_total_delta(total_before, self.total_staked, amount_before, amount_after)
     }
 
-    fn get_time(
-        &mut self,
-        owner: &AccountAddress,
-        curr_time: u64,
-        state_builder: &mut StateBuilder<S>,
-    ) -> ContractResult<u64> {
-        let mut owner_state =
-            self.stake.entry(*owner).or_insert_with(|| StakeState::empty());
-        Ok((curr_time - owner_state.staked_start_at) / &1000u64)
+    /// Principal the owner has staked across their stake position and lots.
+    fn account_principal(&self, owner: &AccountAddress) -> u64 {
+        let position = self.stake.get(owner).map_or(0, |owner_state| owner_state.amount);
+        position.saturating_add(self.lot_principal(owner))
     }
 
-    fn get_reward(
-        &mut self,
-        owner: &AccountAddress,
-        time: u64,
-        state_builder: &mut StateBuilder<S>,
-    ) -> ContractResult<u64> {
-        let mut owner_state =
-            self.stake.entry(*owner).or_insert_with(|| StakeState::empty());
-        
-        Ok(owner_state.amount * time / SECOND_PER_YEAR)
+    /// Principal the owner has staked in lots.
+    fn lot_principal(&self, owner: &AccountAddress) -> u64 {
+        self.lots.get(owner).map_or(0, |owner_lots| {
+            owner_lots.iter().fold(0u64, |total, (_, lot)| total.saturating_add(lot.amount))
+        })
     }
-}
 
-#[init(contract = "token-staking")]
-fn contract_init<S: HasStateApi>(
-    _ctx: &impl HasInitContext,
-    state_builder: &mut StateBuilder<S>,
-) -> InitResult<State<S>> {
-    // Construct the initial contract state.
-    Ok(State::empty(state_builder))
-}
+    /// Rejects with `StakeCapExceeded` if adding `amount` takes the owner's
+    /// combined principal past `max_stake_per_account`.
+    fn ensure_within_cap(&self, owner: &AccountAddress, amount: u64) -> ContractResult<()> {
+        ensure!(
+            self.max_stake_per_account
+                .is_none_or(|cap| self.account_principal(owner).saturating_add(amount) <= cap),
+            CustomContractError::StakeCapExceeded.into()
+        );
+        Ok(())
+    }
 
-#[derive(Serial, Deserial, SchemaType)]
-struct StakeParams {
-    owner:  AccountAddress,
-    amount: u64,
-    token_contract_address: ContractAddress
-}
+    /// Time until which a stake made at `staked_time` is locked under the
+    /// current terms.
+    fn lock_until(&self, staked_time: u64) -> u64 {
+        staked_time.saturating_add(self.min_lock_seconds.saturating_mul(1000))
+    }
 
-#[receive(
-    contract = "token",
-    name = "stake",
-    parameter = "StakeParams",
-    error = "ContractError",
-    mutable
-)]
-fn stake_token<S: HasStateApi>(
-    ctx: &impl HasReceiveContext,
-    host: &mut impl HasHost<State<S>, StateApiType = S>,
-) -> ContractResult<()> {
-    let params: StakeParams = ctx.parameter_cursor().get()?;
-    let sender = ctx.sender();
+    /// Purges the owner's position and updates every aggregate over positions
+    /// accordingly. Any reward it accrued must have been taken out first.
+    fn remove_staker(&mut self, owner: &AccountAddress) -> ContractResult<()> {
+        let owner_state = match self.stake.remove_and_get(owner) {
+            Some(owner_state) => owner_state,
+            None => return Ok(()),
+        };
+        let total_before = self.total_staked;
+        self.total_staked =
+            self.total_staked.checked_sub(owner_state.amount).ok_or(CustomContractError::AmountOverflow)?;
+        if owner_state.amount > 0 && self.lot_principal(owner) == 0 {
+            self.staker_count -= 1;
+        }
+        ensure_total_delta(total_before, self.total_staked, owner_state.amount, 0)
+    }
 
-    ensure!(sender.matches_account(&params.owner), ContractError::Unauthorized);
-    ensure_balance(host, TOKEN_ID, &params.token_contract_address, params.amount, ctx)?;
-    ensure_is_operator(host, ctx, &params.token_contract_address)?;
+    /// Withdraws `amount` of the owner's principal, removing the position
+    /// once nothing is left staked or unbonding. The position must have been
+    /// settled first.
+    fn withdraw_token(&mut self, owner: &AccountAddress, amount: u64) -> ContractResult<()> {
+        let mut owner_state = match self.stake.get_mut(owner) {
+            Some(owner_state) => owner_state,
+            None => return Ok(()),
+        };
+        let amount_before = owner_state.amount;
+        let amount_after = amount_before.checked_sub(amount).ok_or(CustomContractError::NoBalance)?;
+        if amount_after == 0 && owner_state.unbonding_amount == 0 {
+            drop(owner_state);
+            return self.remove_staker(owner);
+        }
+        owner_state.amount = amount_after;
+        drop(owner_state);
+        let total_before = self.total_staked;
+        self.total_staked =
+            self.total_staked.checked_sub(amount).ok_or(CustomContractError::AmountOverflow)?;
+        if amount_before > 0 && amount_after == 0 && self.lot_principal(owner) == 0 {
+            self.staker_count -= 1;
+        }
+        ensure_total_delta(total_before, self.total_staked, amount_before, amount_after)
+    }
 
-    let (state, builder) = host.state_and_builder();
-    let slot_time = ctx.metadata().slot_time();
-    state.insert_token(&params.owner, params.amount, Timestamp::timestamp_millis(&slot_time), builder)?;
+    /// Moves `amount` of the owner's principal into unbonding at `now`, where
+    /// it stops accruing reward. The reward accrued so far stays claimable.
+    fn request_unstake(&mut self, owner: &AccountAddress, amount: u64, now: u64) -> ContractResult<()> {
+        self.settle(owner, now)?;
+        let mut owner_state = self.stake.get_mut(owner).ok_or(CustomContractError::TokenNotFound)?;
+        let amount_before = owner_state.amount;
+        let amount_after = amount_before.checked_sub(amount).ok_or(CustomContractError::NoBalance)?;
+        owner_state.amount = amount_after;
+        owner_state.unbonding_amount =
+            owner_state.unbonding_amount.checked_add(amount).ok_or(CustomContractError::AmountOverflow)?;
+        owner_state.unbonding_at = now;
+        drop(owner_state);
+        let total_before = self.total_staked;
+        self.total_staked =
+            self.total_staked.checked_sub(amount).ok_or(CustomContractError::AmountOverflow)?;
+        if amount_before > 0 && amount_after == 0 && self.lot_principal(owner) == 0 {
+            self.staker_count -= 1;
+        }
+        ensure_total_delta(total_before, self.total_staked, amount_before, amount_after)
+    }
 
-    Ok(())
-}
+    /// Moves the reward accrued by the owner's current amount into their
+    /// `accrued` ledger and restarts accrual at `now`. Must be called before
+    /// every change to a position's amount, so that no reward is lost or
+    /// granted on the new amount for time before the change.
+    fn settle(&mut self, owner: &AccountAddress, now: u64) -> ContractResult<()> {
+        self.update_pool(now);
+        let (accrued, remainder) = self.get_reward(owner, now)?;
+        self.rounding_loss += remainder;
+        let released = self.stake.get(owner).map_or(0, |owner_state| self.unsettled_of(&owner_state, now));
+        self.release_unsettled(released);
+        let reward_per_token = self.reward_per_token;
+        if let Some(mut owner_state) = self.stake.get_mut(owner) {
+            self.total_liabilities += u128::from(accrued.saturating_sub(owner_state.accrued));
+            owner_state.accrued = accrued;
+            owner_state.last_claimed_at = now;
+            owner_state.reward_per_token_paid = reward_per_token;
+        }
+        Ok(())
+    }
 
-#[derive(Serial, Deserial, SchemaType)]
-struct UnStakeParams {
-    owner:  AccountAddress,
-    token_contract_address: ContractAddress
-}
+    /// Accumulates the pool-rate emission and the unsettled accrual up to
+    /// `now`. Must be called before every change to `total_staked` or
+    /// `total_ccd_staked`, so that accrual before the change is computed from
+    /// the totals before it.
+    fn update_pool(&mut self, now: u64) {
+        if now > self.unsettled_updated_at {
+            self.unsettled_accrual += self.unsettled_between(self.unsettled_updated_at, now);
+            self.unsettled_updated_at = now;
+        }
+        if self.reward_per_second.is_some() && now > self.pool_updated_at {
+            self.reward_per_token = self.reward_per_token_at(now);
+            self.pool_updated_at = now;
+        }
+    }
 
-#[receive(
-    contract = "token-staking",
-    name = "unstake",
-    parameter = "UnStakeParams",
-    error = "ContractError",
-    mutable
-)]
-fn unstake_token<S: HasStateApi>(
-    ctx: &impl HasReceiveContext,
-    host: &mut impl HasHost<State<S>, StateApiType = S>,
-) -> ContractResult<()> {
-    let params: UnStakeParams = ctx.parameter_cursor().get()?;
-    let sender = ctx.sender();
+    /// Value of the pool-rate accumulator at `now`.
+    fn reward_per_token_at(&self, now: u64) -> u128 {
+        let rate = match self.reward_per_second {
+            Some(rate) if self.total_staked > 0 && now > self.pool_updated_at => rate,
+            _ => return self.reward_per_token,
+        };
+        // Token-milliseconds emitted, respecting halvings, seasons and
+        // no-accrual windows like per-stake accrual does.
+        let emitted = self.weighted_accrual(rate, self.pool_updated_at, now);
+        self.reward_per_token
+            + emitted * REWARD_PER_TOKEN_PRECISION / (1000 * u128::from(self.total_staked))
+    }
 
-    ensure!(sender.matches_account(&params.owner), ContractError::Unauthorized);
+    /// Reward accrued by all positions between two millisecond timestamps
+    /// under the current totals, in units of `1 / reward_denominator` tokens.
+    fn unsettled_between(&self, start: u64, end: u64) -> u128 {
+        if start >= end {
+            return 0;
+        }
+        let ccd = self.reward_weighted(self.ccd_value(self.total_ccd_staked), start, end, None);
+        let staked = match self.reward_per_second {
+            None => self.reward_weighted(self.total_staked, start, end, None),
+            Some(_) if self.total_staked == 0 || !self.rewards_enabled => 0,
+            Some(rate) => self.weighted_accrual(rate, start, end) * self.reward_denominator() / 1000,
+        };
+        ccd + staked
+    }
 
-    let (state, builder) = host.state_and_builder();
-    state.remove_token(&params.owner, builder)?;
+    /// Part of `unsettled_accrual` accrued by a stake position or lot since it
+    /// was last settled.
+    fn unsettled_of(&self, position: &StakeState, now: u64) -> u128 {
+        match self.reward_per_second {
+            None => self.reward_weighted(position.amount, position.last_claimed_at, now, None),
+            Some(_) => u128::from(self.position_reward(position, now).0) * self.reward_denominator(),
+        }
+    }
 
-    let reward = calculate_reward(host, ctx, &params.owner).unwrap();
-    Cis2Client::transfer(
-        host,
-        TOKEN_ID,
-        params.token_contract_address,
-        concordium_cis2::TokenAmountU64(reward),
-        ctx.owner(),
-        concordium_cis2::Receiver::Account(ctx.invoker()),
-    );
+    /// Takes the accrual of a position out of `unsettled_accrual` once its
+    /// reward is settled. `update_pool` must have been called at the time of
+    /// settlement first.
+    fn release_unsettled(&mut self, accrual: u128) {
+        self.unsettled_accrual = self.unsettled_accrual.saturating_sub(accrual);
+    }
 
-    Ok(())
-}
+    /// Reward owed at `now`: `total_liabilities` plus the estimated reward
+    /// accrued since positions were last settled.
+    fn liabilities_at(&self, now: u64) -> u128 {
+        let unsettled = self.unsettled_accrual + self.unsettled_between(self.unsettled_updated_at, now);
+        self.total_liabilities + unsettled / self.reward_denominator()
+    }
 
-#[derive(Serial, Deserial, SchemaType)]
-struct ClaimParams {
-    owner:  AccountAddress,
-    token_contract_address: ContractAddress
-}
+    /// Reward accrued by a position since it was last settled, and the
+    /// remainder lost to rounding. Pool-rate rounding is not tracked.
+    fn position_reward(&self, position: &StakeState, now: u64) -> (u64, u128) {
+        if !self.rewards_enabled {
+            return (0, 0);
+        }
+        if self.reward_per_second.is_none() {
+            return self.reward_between(position.amount, position.last_claimed_at, now);
+        }
+        let per_token = self.reward_per_token_at(now).saturating_sub(position.reward_per_token_paid);
+        let reward = u128::from(position.amount) * per_token / REWARD_PER_TOKEN_PRECISION;
+        (reward.min(u128::from(u64::MAX)) as u64, 0)
+    }
 
-#[receive(
-    contract = "token-staking",
-    name = "claim",
-    parameter = "ClaimParams",
-    error = "ContractError",
-    mutable
-)]
-fn claim_reward<S: HasStateApi>(
-    ctx: &impl HasReceiveContext,
-    host: &mut impl HasHost<State<S>, StateApiType = S>,
-) -> ContractResult<()> {
-    let params: ClaimParams = ctx.parameter_cursor().get()?;
-    let sender = ctx.sender();
+    /// Settles the owner's position at `now` and takes everything it has
+    /// accrued out of the ledger for payout, net of its adjustment.
+    fn take_reward(&mut self, owner: &AccountAddress, now: u64) -> ContractResult<u64> {
+        self.settle(owner, now)?;
+        let mut owner_state = match self.stake.get_mut(owner) {
+            Some(owner_state) => owner_state,
+            None => return Ok(0),
+        };
+        let accrued = core::mem::take(&mut owner_state.accrued);
+        let (claimable, remaining) = effective_claimable(accrued, owner_state.adjustment);
+        owner_state.adjustment = remaining;
+        drop(owner_state);
+        // A penalty offsets reward that is then no longer owed.
+        self.forfeit_reward(accrued.saturating_sub(claimable));
+        Ok(claimable)
+    }
 
-    ensure!(sender.matches_account(&params.owner), ContractError::Unauthorized);
+    /// Drops `amount` of settled reward that will not be paid out from the
+    /// liabilities.
+    fn forfeit_reward(&mut self, amount: u64) {
+        self.total_liabilities = self.total_liabilities.saturating_sub(u128::from(amount));
+    }
 
-    let (state, builder) = host.state_and_builder();
-    state.remove_token(&params.owner, builder)?;
+    /// Counts a claim against the per-block throttle, rejecting it if the
+    /// block processing it has already reached `max_claims_per_block`. All
+    /// transactions in a block share its slot time, which identifies it.
+    fn record_claim(&mut self, now: u64) -> ContractResult<()> {
+        let max = match self.max_claims_per_block {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        let (block, count) = self.block_claims;
+        let count = if block == now { count } else { 0 };
+        ensure!(count < max, CustomContractError::ClaimThrottled.into());
+        self.block_claims = (now, count + 1);
+        Ok(())
+    }
 
-    let reward = calculate_reward(host, ctx, &params.owner).unwrap();
-    Cis2Client::transfer(
-        host,
-        TOKEN_ID,
-        params.token_contract_address,
-        concordium_cis2::TokenAmountU64(reward),
-        ctx.owner(),
-        concordium_cis2::Receiver::Account(ctx.invoker()),
-    );
+    /// Credits `referrer` with the referral bonus on a stake of `amount`, if
+    /// it is the first stake naming a referrer for the owner. The first
+    /// `referrer` named for an account sticks and is credited only once, so
+    /// that the bonus cannot be farmed by staking and unstaking in a loop.
+    fn credit_referral(
+        &mut self,
+        owner: &AccountAddress,
+        referrer: Option<AccountAddress>,
+        amount: u64,
+    ) -> ContractResult<()> {
+        let referrer = match referrer {
+            Some(referrer) => referrer,
+            None => return Ok(()),
+        };
+        ensure!(referrer != *owner, CustomContractError::SelfReferral.into());
+        if self.referrers.get(owner).is_some() {
+            return Ok(());
+        }
+        let _ = self.referrers.insert(*owner, referrer);
+        let bonus = (u128::from(amount) * u128::from(self.referral_bonus_bps) / 10_000) as u64;
+        if bonus > 0 && self.rewards_enabled {
+            *self.referral_rewards.entry(referrer).or_insert(0) += bonus;
+            self.total_liabilities += u128::from(bonus);
+        }
+        Ok(())
+    }
 
-    Ok(())
-}
+    /// Remembers `key` for the owner's stakes, returning whether it was
+    /// already seen. Only the last `MAX_IDEMPOTENCY_KEYS` keys are kept.
+    fn check_stake_key(&mut self, owner: &AccountAddress, key: u64) -> bool {
+        let mut keys = self.stake_keys.entry(*owner).or_default();
+        if keys.contains(&key) {
+            return true;
+        }
+        if keys.len() >= MAX_IDEMPOTENCY_KEYS {
+            keys.remove(0);
+        }
+        keys.push(key);
+        false
+    }
 
-fn calculate_reward<S: HasStateApi>(
-    host: &mut impl HasHost<State<S>, StateApiType = S>,
-    ctx: &impl HasReceiveContext<()>,
-    owner: &AccountAddress,
-) -> Result<u64, CustomContractError> {
-    let slot_time = ctx.metadata().slot_time();
-    let (state, state_builder) = host.state_and_builder();
-    let time = state.get_time(owner, concordium_std::Timestamp::timestamp_millis(&slot_time), state_builder).unwrap();
-    let reward = state.get_reward(owner, time, state_builder).unwrap();
+    /// Rejects if the entrypoint guarded by `flag` is paused.
+    fn ensure_not_paused(&self, flag: u8) -> ContractResult<()> {
+        ensure!(self.pause_flags & flag == 0, CustomContractError::ContractPaused.into());
+        Ok(())
+    }
 
-    Ok(reward)
-}
+    /// Moves an idle position into `status` for the duration of an operation,
+    /// including its calls out to the token contract, rejecting if another
+    /// operation on it is still in flight. Rejects with
+    /// `TokenNotFound` if the owner has no position, so that operations never
+    /// create one.
+    fn begin_operation(
+        &mut self,
+        owner: &AccountAddress,
+        status: PositionStatus,
+    ) -> ContractResult<()> {
+        let mut owner_state = self.stake.get_mut(owner).ok_or(CustomContractError::TokenNotFound)?;
+        ensure!(owner_state.status == PositionStatus::Active, CustomContractError::PositionBusy.into());
+        owner_state.status = status;
+        Ok(())
+    }
 
-pub struct Cis2Client;
+    /// Marks the start of an entrypoint that calls out to the token contract,
+    /// rejecting the call if it re-enters another such entrypoint. State is
+    /// saved before every outgoing call, so a callback sees the flag set.
+    /// The flag needs no clearing on rejection, which rolls back the state.
+    fn enter_call(&mut self) -> ContractResult<()> {
+        ensure!(!self.locked, CustomContractError::Reentrancy.into());
+        self.locked = true;
+        Ok(())
+    }
 
-impl Cis2Client {
-    pub(crate) fn is_operator_of<S: HasStateApi>(
-        host: &mut impl HasHost<State<S>, StateApiType = S>,
-        owner: Address,
-        current_contract_address: ContractAddress,
-        token_contract_address: &ContractAddress,
-    ) -> Result<bool, Cis2ClientError> {
-        let params = &OperatorOfQueryParams {
-            queries: vec![OperatorOfQuery {
-                owner,
-                address: Address::Contract(current_contract_address),
-            }],
-        };
+    /// Marks the successful end of an entrypoint started with `enter_call`.
+    fn exit_call(&mut self) { self.locked = false; }
 
-        let parsed_res: OperatorOfQueryResponse = Cis2Client::invoke_contract_read_only(
-            host,
-            token_contract_address,
-            OPERATOR_OF_ENTRYPOINT_NAME,
-            params,
-        )?;
+    /// Records an action by the owner on their position at `now`, for the
+    /// inactivity check of `reclaimUnclaimedAfter`.
+    fn record_interaction(&mut self, owner: &AccountAddress, now: u64) {
+        if let Some(mut owner_state) = self.stake.get_mut(owner) {
+            owner_state.last_interaction_at = now;
+        }
+    }
 
-        let is_operator = parsed_res
-            .0
-            .first()
-            .ok_or(Cis2ClientError::InvokeContractError)?
-            .to_owned();
+    /// Returns a position to `Active` once its operation has completed.
+    fn end_operation(&mut self, owner: &AccountAddress) {
+        if let Some(mut owner_state) = self.stake.get_mut(owner) {
+            owner_state.status = PositionStatus::Active;
+        }
+    }
 
-        Ok(is_operator)
+    /// Records reward that could not be paid out on unstake, so that it can
+    /// still be claimed within the residual claim window. Without a window it
+    /// stays accrued on the owner's stake position instead, which is kept
+    /// open for it.
+    fn record_residual(&mut self, owner: &AccountAddress, amount: u64, now: u64) {
+        if amount == 0 {
+            return;
+        }
+        if self.residual_claim_window == 0 {
+            let mut owner_state = self.stake.entry(*owner).or_insert_with(StakeState::empty);
+            owner_state.accrued = owner_state.accrued.saturating_add(amount);
+            // The exit is an action by the owner, so the reward kept here
+            // counts as freshly claimed for `reclaimUnclaimedAfter`.
+            owner_state.last_claimed_at = now;
+            owner_state.last_interaction_at = now;
+            return;
+        }
+        let expires_at = now + self.residual_claim_window;
+        let mut residual = self
+            .pending_after_unstake
+            .entry(*owner)
+            .or_insert_with(|| ResidualReward {
+                amount: 0,
+                expires_at,
+            });
+        // An expired residual is forfeited, so it is replaced rather than
+        // revived by the new one.
+        let expired = if now >= residual.expires_at { core::mem::take(&mut residual.amount) } else { 0 };
+        residual.amount += amount;
+        residual.expires_at = expires_at;
+        drop(residual);
+        self.forfeit_reward(expired);
     }
 
-    pub(crate) fn has_balance<S: HasStateApi>(
-        host: &mut impl HasHost<State<S>, StateApiType = S>,
-        token_id: ContractTokenId,
-        token_contract_address: &ContractAddress,
-        amount: u64,
-        owner: Address,
-    ) -> Result<bool, Cis2ClientError> {
-        let params = ContractBalanceOfQueryParams {
-            queries: vec![BalanceOfQuery {
-                token_id,
-                address: owner,
-            }],
+    /// Starts releasing claimed `amount` to the owner under the vesting
+    /// schedule.
+    fn record_vesting(&mut self, owner: &AccountAddress, amount: u64, now: u64) {
+        if amount == 0 {
+            return;
+        }
+        self.vesting_entries.entry(*owner).or_default().push(VestingEntry {
+            total:    amount,
+            released: 0,
+            start:    now,
+        });
+    }
+
+    /// Splits `amount` into the configured installments, released one
+    /// interval apart starting one interval after `now`. The last installment
+    /// carries the remainder of the split.
+    fn schedule_installments(&mut self, owner: &AccountAddress, amount: u64, now: u64) {
+        let config = match self.installments {
+            Some(config) if amount > 0 => config,
+            _ => return,
         };
+        let count = u64::from(config.count);
+        let share = amount / count;
+        let interval = config.interval_seconds.saturating_mul(1000);
+        let mut installments = self.pending_installments.entry(*owner).or_default();
+        for i in 1..=count {
+            let amount = if i == count { amount - share * (count - 1) } else { share };
+            installments.push(Installment {
+                amount,
+                release_at: now.saturating_add(interval.saturating_mul(i)),
+            });
+        }
+    }
 
-        let parsed_res: ContractBalanceOfQueryResponse = Cis2Client::invoke_contract_read_only(
-            host,
-            token_contract_address,
-            BALANCE_OF_ENTRYPOINT_NAME,
-            &params,
-        )?;
+    /// Part of a vesting entry released by `now`.
+    fn vested_amount(&self, entry: &VestingEntry, now: u64) -> u64 {
+        let vesting = match self.vesting {
+            Some(vesting) => vesting,
+            None => return entry.total,
+        };
+        let elapsed = now.saturating_sub(entry.start);
+        if elapsed < vesting.cliff_seconds.saturating_mul(1000) {
+            return 0;
+        }
+        let duration = vesting.duration_seconds.saturating_mul(1000);
+        if elapsed >= duration {
+            return entry.total;
+        }
+        (u128::from(entry.total) * u128::from(elapsed) / u128::from(duration)) as u64
+    }
 
-        let is_operator = parsed_res
-            .0
-            .first()
-            .ok_or(Cis2ClientError::InvokeContractError)?
-            .to_owned();
+    /// Seconds of reward accrual since the stake started, excluding any time
+    /// covered by the no-accrual windows.
+    fn get_time(&self, owner: &AccountAddress, curr_time: u64) -> ContractResult<u64> {
+        let start = self.stake.get(owner).map_or(curr_time, |owner_state| owner_state.staked_start_at);
+        Ok(self.elapsed_seconds(start, curr_time))
+    }
 
-        Result::Ok(is_operator.cmp(&TokenAmountU64(amount)).is_ge())
+    /// Seconds of reward accrual between two millisecond timestamps.
+    fn elapsed_seconds(&self, start: u64, curr_time: u64) -> u64 {
+        self.accrual_millis(start, curr_time) / 1000
     }
 
-    pub(crate) fn transfer<S: HasStateApi>(
-        host: &mut impl HasHost<State<S>, StateApiType = S>,
-        token_id: TokenIdUnit,
-        token_contract_address: ContractAddress,
-        amount: ContractTokenAmount,
-        from: AccountAddress,
-        to: Receiver,
-    ) -> Result<bool, Cis2ClientError> {
-        let params: TransferParameter = TransferParams(vec![Transfer {
-            token_id,
-            amount,
-            from: concordium_std::Address::Account(from),
-            data: AdditionalData::empty(),
-            to,
-        }]);
+    /// Milliseconds of reward accrual between two millisecond timestamps.
+    /// Time before `program_start` or outside of the season in effect at the
+    /// time does not count.
+    fn accrual_millis(&self, start: u64, curr_time: u64) -> u64 {
+        self.terms_segments(start, curr_time)
+            .iter()
+            .map(|&(start, end, _, season)| self.accrual_millis_in(start, end, season))
+            .sum()
+    }
 
-        Cis2Client::invoke_contract_read_only(
-            host,
-            &token_contract_address,
-            TRANSFER_ENTRYPOINT_NAME,
-            &params,
-        )?;
+    /// Milliseconds of reward accrual between two millisecond timestamps
+    /// under `season`.
+    fn accrual_millis_in(&self, start: u64, curr_time: u64, season: Option<Season>) -> u64 {
+        let start = start.max(self.program_start);
+        let (start, curr_time) = match season {
+            Some(season) => (start.max(season.start), curr_time.min(season.end)),
+            None => (start, curr_time),
+        };
+        if start >= curr_time {
+            return 0;
+        }
+        let excluded = excluded_millis(&self.no_accrual_windows, start, curr_time);
+        curr_time.saturating_sub(start).saturating_sub(excluded)
+    }
 
-        Result::Ok(true)
+    /// Reward accrued by the owner's stake up to `curr_time`, including
+    /// settled reward, together with the remainder lost to rounding it down,
+    /// in units of `1 / reward_denominator()` tokens.
+    fn get_reward(&self, owner: &AccountAddress, curr_time: u64) -> ContractResult<(u64, u128)> {
+        let owner_state = match self.stake.get(owner) {
+            Some(owner_state) => owner_state,
+            None => return Ok((0, 0)),
+        };
+        Ok(self.stake_reward(owner, &owner_state, curr_time))
     }
-    
-    fn invoke_contract_read_only<S: HasStateApi, R: Deserial, P: Serial>(
-        host: &mut impl HasHost<State<S>, StateApiType = S>,
-        contract_address: &ContractAddress,
-        entrypoint_name: &str,
-        params: &P,
-    ) -> Result<R, Cis2ClientError> {
-        let invoke_contract_result = host
-            .invoke_contract_read_only(
-                contract_address,
-                params,
-                EntrypointName::new(entrypoint_name).unwrap_abort(),
-                Amount::from_ccd(0),
+
+    /// Reward accrued by the owner's stake position up to `curr_time`,
+    /// including settled reward, and the remainder lost to rounding.
+    fn stake_reward(&self, owner: &AccountAddress, owner_state: &StakeState, curr_time: u64) -> (u64, u128) {
+        // Tiers replace the per-stake rate. Each part of the period since the
+        // position was last settled earns the rate of the tier reached then.
+        let (reward, remainder) = if !self.apr_tiers.is_empty() && self.reward_per_second.is_none() {
+            self.reward_between_with_tiers(
+                owner_state.amount,
+                owner_state.last_claimed_at,
+                curr_time,
+                Some(owner_state.deposited_at),
             )
-            .map_err(|_e| Cis2ClientError::InvokeContractError)?;
-        let mut invoke_contract_res = match invoke_contract_result {
-            Some(s) => s,
-            None => return Result::Err(Cis2ClientError::InvokeContractError),
+        } else {
+            self.position_reward(owner_state, curr_time)
         };
-        let parsed_res =
-            R::deserial(&mut invoke_contract_res).map_err(|_e| Cis2ClientError::ParseResult)?;
+        let reward = u128::from(reward) * u128::from(self.reward_multiplier_bps(owner, curr_time)) / 10_000;
+        (owner_state.accrued.saturating_add(reward as u64), remainder)
+    }
 
-        Ok(parsed_res)
+    /// Rate in basis points of the highest tier reached by a position last
+    /// deposited into at `deposited_at`, where reaching a tier's
+    /// `min_seconds` exactly counts. Positions below every tier get the
+    /// lowest. `None` without tiers.
+    fn tier_rate_bps(&self, deposited_at: u64, now: u64) -> Option<u16> {
+        let staked_for = now.saturating_sub(deposited_at) / 1000;
+        let lowest = self.apr_tiers.first()?;
+        let tier = self.apr_tiers.iter().rev().find(|(min_seconds, _)| staked_for >= *min_seconds);
+        Some(tier.unwrap_or(lowest).1)
+    }
+
+    /// Splits `[start, end)` at the tier thresholds reached within it by a
+    /// position last deposited into at `deposited_at`, returning
+    /// `(start, end, rate_bps)` for each part in order.
+    fn tier_segments(&self, deposited_at: u64, start: u64, end: u64) -> Vec<(u64, u64, u16)> {
+        let mut segments = Vec::new();
+        let mut segment_start = start;
+        for (min_seconds, _) in self.apr_tiers.iter() {
+            let threshold = deposited_at.saturating_add(min_seconds.saturating_mul(1000));
+            if threshold <= segment_start || threshold >= end {
+                continue;
+            }
+            let rate_bps = self.tier_rate_bps(deposited_at, segment_start).unwrap_or(0);
+            segments.push((segment_start, threshold, rate_bps));
+            segment_start = threshold;
+        }
+        if segment_start < end {
+            let rate_bps = self.tier_rate_bps(deposited_at, segment_start).unwrap_or(0);
+            segments.push((segment_start, end, rate_bps));
+        }
+        segments
+    }
+
+    /// Early exits still held against the owner at `now`, after forgiving
+    /// one per `recovery_seconds` since the last of them.
+    fn early_exit_count(&self, owner: &AccountAddress, now: u64) -> u32 {
+        let (decay, record) = match (self.early_exit_decay, self.early_exits.get(owner)) {
+            (Some(decay), Some(record)) => (decay, *record),
+            _ => return 0,
+        };
+        let recovery_millis = decay.recovery_seconds.saturating_mul(1000);
+        if recovery_millis == 0 {
+            return 0;
+        }
+        let forgiven = now.saturating_sub(record.last_exit_at) / recovery_millis;
+        record.count.saturating_sub(forgiven.min(u64::from(u32::MAX)) as u32)
+    }
+
+    /// Share of the reward rate the owner's stake earns at `now`, in basis
+    /// points, reduced for each early exit held against the owner.
+    fn reward_multiplier_bps(&self, owner: &AccountAddress, now: u64) -> u64 {
+        let penalty_bps = match self.early_exit_decay {
+            Some(decay) => u64::from(decay.penalty_bps),
+            None => return 10_000,
+        };
+        let count = u64::from(self.early_exit_count(owner, now));
+        10_000 - penalty_bps.saturating_mul(count).min(10_000)
+    }
+
+    /// Records an unstake of a position last deposited into at
+    /// `deposited_at`. Unstaking within the early-exit window counts as an
+    /// early exit, while unstaking after it forgives all early exits.
+    fn record_exit(&mut self, owner: &AccountAddress, deposited_at: u64, now: u64) {
+        let decay = match self.early_exit_decay {
+            Some(decay) => decay,
+            None => return,
+        };
+        if now < deposited_at.saturating_add(decay.window_seconds.saturating_mul(1000)) {
+            let count = self.early_exit_count(owner, now).saturating_add(1);
+            let _ = self.early_exits.insert(*owner, EarlyExitRecord {
+                count,
+                last_exit_at: now,
+            });
+        } else {
+            self.early_exits.remove(owner);
+        }
+    }
+
+    /// Value in the staked token of `micro_ccd` under `tokens_per_ccd`.
+    fn ccd_value(&self, micro_ccd: u64) -> u64 {
+        let rate = self.tokens_per_ccd.unwrap_or(0);
+        (u128::from(micro_ccd) * u128::from(rate) / 1_000_000).min(u128::from(u64::MAX)) as u64
+    }
+
+    /// Reward accrued by a CCD position since it was last settled, in the
+    /// staked token, and the remainder lost to rounding.
+    fn ccd_reward(&self, position: &StakeState, now: u64) -> (u64, u128) {
+        self.reward_between(self.ccd_value(position.amount), position.last_claimed_at, now)
+    }
+
+    /// Reward accrued by `amount` between two millisecond timestamps at the
+    /// rates in effect at the time, and the remainder lost to rounding.
+    fn reward_between(&self, amount: u64, start: u64, end: u64) -> (u64, u128) {
+        self.reward_between_with_tiers(amount, start, end, None)
+    }
+
+    /// Reward accrued by `amount` between two millisecond timestamps, and the
+    /// remainder lost to rounding. With the time of the last deposit the
+    /// rates of the tiers reached replace the reward rate, otherwise the
+    /// rates in effect at the time apply.
+    fn reward_between_with_tiers(&self, amount: u64, start: u64, end: u64, tiers_from: Option<u64>) -> (u64, u128) {
+        let weighted = self.reward_weighted(amount, start, end, tiers_from);
+        let denominator = self.reward_denominator();
+        ((weighted / denominator) as u64, weighted % denominator)
+    }
+
+    /// Reward accrued by `amount` between two millisecond timestamps as in
+    /// `reward_between_with_tiers`, in units of `1 / reward_denominator`
+    /// tokens.
+    fn reward_weighted(&self, amount: u64, start: u64, end: u64, tiers_from: Option<u64>) -> u128 {
+        if !self.rewards_enabled {
+            return 0;
+        }
+        self
+            .terms_segments(start, end)
+            .iter()
+            .map(|&(start, end, terms_rate_bps, season)| match tiers_from {
+                Some(deposited_at) => self
+                    .tier_segments(deposited_at, start, end)
+                    .iter()
+                    .map(|&(start, end, rate_bps)| {
+                        self.weighted_accrual_in(amount, start, end, season) * u128::from(rate_bps)
+                    })
+                    .sum(),
+                None => self.weighted_accrual_in(amount, start, end, season) * u128::from(terms_rate_bps),
+            })
+            .sum()
+    }
+
+    /// Splits an `owed` payout, already taken out of the ledgers, into the
+    /// part the reward pool covers under the shortfall policy and the part
+    /// that stays owed. First come, first served pays what the pool holds and
+    /// keeps the rest owed. Pro rata pays every claimer the same share of
+    /// their reward, the pool's coverage of the liabilities at `now`, and
+    /// forfeits the rest, so that later claimers get the same share. The
+    /// liabilities include reward accrued but not yet settled.
+    fn cover_reward(&mut self, owed: u64, now: u64) -> (u64, u64) {
+        match self.shortfall_policy {
+            ShortfallPolicy::FirstComeFirstServed => {
+                let covered = owed.min(self.reward_pool);
+                (covered, owed - covered)
+            }
+            ShortfallPolicy::ProRata => {
+                let liabilities = self.liabilities_at(now).max(u128::from(owed));
+                let pool = u128::from(self.reward_pool);
+                let covered =
+                    if pool >= liabilities { owed } else { (u128::from(owed) * pool / liabilities) as u64 };
+                self.forfeit_reward(owed - covered);
+                (covered, 0)
+            }
+        }
+    }
+
+    /// Token-milliseconds that accrue one token of reward.
+    fn reward_denominator(&self) -> u128 { u128::from(self.reward_period) * 1000 * 10_000 }
+
+    /// Token-milliseconds accrued by `amount` between two millisecond
+    /// timestamps. With a halving schedule the rate halves every
+    /// `halving_interval` seconds since `program_start`, and each part of the
+    /// period is weighted by the rate in effect at that time.
+    fn weighted_accrual(&self, amount: u64, start: u64, end: u64) -> u128 {
+        self.terms_segments(start, end)
+            .iter()
+            .map(|&(start, end, _, season)| self.weighted_accrual_in(amount, start, end, season))
+            .sum()
+    }
+
+    /// Token-milliseconds accrued by `amount` between two millisecond
+    /// timestamps under `season`.
+    fn weighted_accrual_in(&self, amount: u64, start: u64, end: u64, season: Option<Season>) -> u128 {
+        let interval_millis = match self.halving_interval {
+            Some(interval) => interval.saturating_mul(1000),
+            None => return u128::from(amount) * u128::from(self.accrual_millis_in(start, end, season)),
+        };
+
+        let mut weighted = 0u128;
+        let mut segment_start = start;
+        while segment_start < end {
+            let halvings = segment_start.saturating_sub(self.program_start) / interval_millis;
+            if halvings >= MAX_HALVINGS {
+                break;
+            }
+            let next_halving = (halvings + 1).saturating_mul(interval_millis);
+            let segment_end = end.min(self.program_start.saturating_add(next_halving));
+            let millis = self.accrual_millis_in(segment_start, segment_end, season);
+            weighted += (u128::from(amount) * u128::from(millis)) >> halvings;
+            segment_start = segment_end;
+        }
+        weighted
+    }
+
+    /// Splits `[start, end)` at the changes of terms within it, returning
+    /// `(start, end, rate_bps, season)` for each part in order.
+    fn terms_segments(&self, start: u64, end: u64) -> Vec<(u64, u64, u16, Option<Season>)> {
+        let mut segments = Vec::new();
+        if start >= end {
+            return segments;
+        }
+        // Only terms replaced after `start` matter. Walking back from the
+        // latest change visits just those.
+        let mut first = self.past_terms_count;
+        while first > 0 && self.past_terms.get(&(first - 1)).is_some_and(|terms| terms.until > start) {
+            first -= 1;
+        }
+        let mut segment_start = start;
+        for index in first..self.past_terms_count {
+            let terms = match self.past_terms.get(&index) {
+                Some(terms) => terms,
+                None => continue,
+            };
+            if terms.until >= end {
+                segments.push((segment_start, end, terms.rate_bps, terms.season));
+                return segments;
+            }
+            segments.push((segment_start, terms.until, terms.rate_bps, terms.season));
+            segment_start = terms.until;
+        }
+        segments.push((segment_start, end, self.reward_rate_bps, self.season));
+        segments
+    }
+
+    /// Replaces the reward rate and season from `now` on. Reward accrued
+    /// before keeps being computed under the terms replaced here.
+    fn change_terms(&mut self, now: u64, rate_bps: u16, season: Option<Season>) {
+        self.update_pool(now);
+        let _ = self.past_terms.insert(self.past_terms_count, PastTerms {
+            until:    now,
+            rate_bps: self.reward_rate_bps,
+            season:   self.season,
+        });
+        self.past_terms_count += 1;
+        self.reward_rate_bps = rate_bps;
+        self.season = season;
     }
 }
 
-fn ensure_is_operator<S: HasStateApi>(
+/// Length in milliseconds of the overlap between `[start, end)` and the
+/// given non-overlapping windows.
+fn excluded_millis(windows: &[(u64, u64)], start: u64, end: u64) -> u64 {
+    windows
+        .iter()
+        .map(|&(window_start, window_end)| {
+            window_end.min(end).saturating_sub(window_start.max(start))
+        })
+        .sum()
+}
+
+/// Nets an adjustment against accrued reward, flooring the result at zero so
+/// that a penalty never reaches into principal. Returns the claimable reward
+/// and the part of the adjustment left over.
+fn effective_claimable(accrued: u64, adjustment: i64) -> (u64, i64) {
+    let net = i128::from(accrued) + i128::from(adjustment);
+    if net < 0 {
+        (0, net as i64)
+    } else {
+        (net.min(i128::from(u64::MAX)) as u64, 0)
+    }
+}
+
+/// Checks that `total_staked` moved by exactly the change in a position's
+/// amount, so that a bookkeeping bug fails the transaction instead of
+/// silently corrupting the total.
+fn ensure_total_delta(
+    total_before: u64,
+    total_after: u64,
+    amount_before: u64,
+    amount_after: u64,
+) -> ContractResult<()> {
+    let consistent = i128::from(total_after) - i128::from(total_before)
+        == i128::from(amount_after) - i128::from(amount_before);
+    debug_assert!(consistent, "total_staked drifted from the position amounts");
+    ensure!(consistent, CustomContractError::StateInconsistent.into());
+    Ok(())
+}
+
+/// Checks that the windows are bounded in number, non-empty and sorted
+/// without overlapping each other.
+fn validate_no_accrual_windows(windows: &[(u64, u64)]) -> Result<(), CustomContractError> {
+    ensure!(windows.len() <= MAX_NO_ACCRUAL_WINDOWS, CustomContractError::InvalidNoAccrualWindows);
+    let mut previous_end = 0u64;
+    for &(start, end) in windows {
+        ensure!(start < end && start >= previous_end, CustomContractError::InvalidNoAccrualWindows);
+        previous_end = end;
+    }
+    Ok(())
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct InitParams {
+    /// `(start, end)` timestamps in milliseconds during which stakes do not
+    /// accrue reward, e.g. weekends and holidays.
+    no_accrual_windows:       Vec<(u64, u64)>,
+    /// Cap on the reward paid out by a single transaction, as a backstop
+    /// against reward calculation bugs. Reward above the cap is forfeited.
+    /// `None` disables the cap.
+    max_reward_per_tx:        Option<u64>,
+    /// Milliseconds after an unstake during which reward left unpaid by it
+    /// can be claimed with `claimResidual`. With zero it stays accrued on the
+    /// owner's stake position instead, to be paid out by `claim`.
+    residual_claim_window:    u64,
+    /// Decimals of the staked token. CIS-2 only publishes these in the
+    /// off-chain metadata, so they are supplied here.
+    token_decimals:           u8,
+    /// Which events to log.
+    event_level:              EventLevel,
+    /// Hold staked principal and reward tokens in the contract itself, rather
+    /// than leaving principal in the staker's wallet.
+    custody:                  bool,
+    /// Halve the reward rate every this many seconds since init. `None`
+    /// keeps the rate constant.
+    halving_interval_seconds: Option<u64>,
+    /// Account receiving dust swept by `collectDust`.
+    treasury:                 AccountAddress,
+    /// Seconds over which a stake earns `reward_rate_per_year_bps` of its
+    /// amount, the annualization basis of the rate. Usually `SECOND_PER_YEAR`.
+    reward_period_seconds:    u64,
+    /// When an existing stake is topped up, pay out its pending reward and
+    /// accrue on the full new balance from the top-up time. The payout is
+    /// subject to the same pause, throttle, clamp and vesting as `claim`;
+    /// while claims are paused or throttled the reward stays accrued.
+    auto_claim_on_top_up:     bool,
+    /// Cap on the claims processed within a single block, protecting the
+    /// reward pool against coordinated drains. `None` disables the cap.
+    max_claims_per_block:     Option<u32>,
+    /// Release reward paid out by claims, unstakes and residual claims over a
+    /// cliff-then-linear schedule instead of paying it out immediately.
+    /// Unstake reward paid in installments does not vest on top. `None`
+    /// disables vesting.
+    vesting:                  Option<VestingConfig>,
+    /// Pay the reward of an unstake out in installments, claimed with
+    /// `claimInstallment`, to smooth the reward pool outflow. `None` pays it
+    /// out in a lump sum.
+    installments:             Option<InstallmentConfig>,
+    /// Fee withheld from principal returned by `unstake` in custody mode, in
+    /// basis points of the principal.
+    unstake_fee_bps:          u16,
+    /// Whether the unstake fee goes to the treasury or back into the reward
+    /// pool.
+    fee_destination:          FeeDestination,
+    /// How payouts are cut when the reward pool cannot cover all pending
+    /// reward.
+    shortfall_policy:         ShortfallPolicy,
+    /// Only accrue reward within this season. `None` accrues without a time
+    /// limit.
+    season:                   Option<Season>,
+    /// Bonus credited to a referrer once per account they referred, on its
+    /// first referred stake, in basis points of the staked amount, paid from
+    /// the reward pool via `claimReferralReward`.
+    referral_bonus_bps:       u16,
+    /// Time in milliseconds at which the program starts, allowing stakes to
+    /// be made ahead of it. `None` starts it at init.
+    program_start:            Option<u64>,
+    /// Seconds without a deposit or action by the owner after which the admin
+    /// can return a position's unclaimed reward to the pool. `None` disables
+    /// reclaiming.
+    reclaim_after_seconds:    Option<u64>,
+    /// Distribute a fixed emission of this many tokens per second across all
+    /// stakers by their share of the total staked, instead of accruing each
+    /// stake at the per-stake rate. `None` uses the per-stake rate.
+    reward_per_second:        Option<u64>,
+    /// Annual reward rate in basis points of the staked amount, applied over
+    /// `reward_period_seconds`. 10_000 pays the whole amount once per period.
+    reward_rate_per_year_bps: u16,
+    /// Pay rewards at all. Disable for pure staking, e.g. for governance
+    /// weight, which needs no reward pool.
+    rewards_enabled:          bool,
+    /// Seconds new stakes are locked for before they can be unstaked.
+    /// Claiming stays possible during the lock.
+    min_lock_seconds:         u64,
+    /// Reduce the reward rate of accounts that repeatedly unstake soon after
+    /// depositing. `None` disables it.
+    early_exit_decay:         Option<EarlyExitDecay>,
+    /// Attach a memo identifying the payout kind and account to the
+    /// additional data of reward, principal and fee transfers.
+    payout_memo:              bool,
+    /// Contract of the staked token. Only this contract is ever invoked.
+    token_contract:           ContractAddress,
+    /// Seconds unstaked principal cools down for before it can be withdrawn.
+    /// Zero allows unstaking in one step.
+    cooldown_seconds:         u64,
+    /// Cap the principal each account holds across its stake position and
+    /// lots. `None` leaves it uncapped.
+    max_stake_per_account:    Option<u64>,
+    /// Reject deposits opening a position below this amount. Deposits onto
+    /// an existing position may be smaller.
+    min_stake_amount:         u64,
+    /// Loyalty tiers of `(min_seconds, bps)`, strictly ascending by
+    /// `min_seconds`. A stake position earns the rate of the highest tier
+    /// whose `min_seconds` it has been staked for since its last deposit,
+    /// instead of `reward_rate_per_year_bps`, from the time it reaches it.
+    apr_tiers:                Vec<(u64, u16)>,
+    /// Value of one CCD in smallest units of the staked token, enabling
+    /// `stakeCcd`. CCD positions accrue reward and are held to the minimum
+    /// stake and the cap at this value. `None` disables CCD staking.
+    tokens_per_ccd:           Option<u64>,
+}
+
+#[init(
+    contract = "token-staking",
+    parameter = "InitParams",
+    error = "ContractError",
+    event = "StakingEvent"
+)]
+fn contract_init<S: HasStateApi>(
+    ctx: &impl HasInitContext,
+    state_builder: &mut StateBuilder<S>,
+) -> ContractResult<State<S>> {
+    let params: InitParams = parse_parameter(ctx)?;
+    validate_no_accrual_windows(&params.no_accrual_windows)?;
+    ensure!(params.halving_interval_seconds != Some(0), CustomContractError::InvalidHalvingInterval.into());
+    ensure!(params.reward_period_seconds > 0, CustomContractError::ZeroTimeBasis.into());
+    ensure!(
+        params.vesting.is_none_or(|vesting| vesting.cliff_seconds <= vesting.duration_seconds),
+        CustomContractError::InvalidVesting.into()
+    );
+    ensure!(
+        params.installments.is_none_or(|installments| installments.count > 0),
+        CustomContractError::InvalidInstallments.into()
+    );
+    ensure!(params.unstake_fee_bps <= 10_000, CustomContractError::InvalidFee.into());
+    ensure!(
+        params.season.is_none_or(|season| season.start < season.end),
+        CustomContractError::InvalidSeason.into()
+    );
+    ensure!(params.referral_bonus_bps <= 10_000, CustomContractError::InvalidReferralBonus.into());
+    ensure!(
+        params.early_exit_decay.is_none_or(|decay| decay.penalty_bps <= 10_000),
+        CustomContractError::InvalidEarlyExitDecay.into()
+    );
+    ensure!(
+        params.apr_tiers.windows(2).all(|tiers| tiers[0].0 < tiers[1].0),
+        CustomContractError::InvalidTiers.into()
+    );
+
+    // Construct the initial contract state.
+    let program_start = params.program_start.unwrap_or_else(|| current_time(ctx));
+    Ok(State::empty(state_builder, params, ctx.init_origin(), program_start))
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct StakeParams {
+    owner:  AccountAddress,
+    amount: u64,
+    token_contract_address: ContractAddress,
+    /// Key identifying this submission, so that a retried or relayed stake
+    /// with the same key is a no-op instead of staking twice.
+    idempotency_key: Option<u64>,
+    /// Account that referred the owner, credited with the referral bonus.
+    referrer: Option<AccountAddress>
+}
+
+#[receive(
+    contract = "token-staking",
+    name = "stake",
+    parameter = "StakeParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn stake_token<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
     host: &mut impl HasHost<State<S>, StateApiType = S>,
-    ctx: &impl HasReceiveContext<()>,
-    token_contract_address: &ContractAddress,
-) -> Result<(), CustomContractError> {
-    let is_operator = Cis2Client::is_operator_of(
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: StakeParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    let sender = ctx.sender();
+    host.state().ensure_not_paused(PAUSE_STAKE)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(sender.matches_account(&params.owner), ContractError::Unauthorized);
+    if let Some(key) = params.idempotency_key {
+        if host.state_mut().check_stake_key(&params.owner, key) {
+            host.state_mut().exit_call();
+            return Ok(());
+        }
+    }
+    ensure_supports_cis2(host, &params.token_contract_address)?;
+    ensure_balance(host, TOKEN_ID, &params.token_contract_address, params.amount, ctx)?;
+    // In custody mode the token contract authorizes the transfer into custody
+    // itself, so the separate operator query is redundant.
+    if !host.state().custody {
+        ensure_is_operator(host, ctx, &params.token_contract_address)?;
+    }
+
+    let now = current_time(ctx);
+    let staked = host.state().stake.get(&params.owner).map_or(0, |owner_state| owner_state.amount);
+    // The auto-claim is a claim like any other: while claims are paused or
+    // the block's claims are used up it is skipped, and the reward stays
+    // accrued on the position.
+    let auto_claim = host.state().auto_claim_on_top_up
+        && staked > 0
+        && host.state().ensure_not_paused(PAUSE_CLAIM).is_ok()
+        && host.state_mut().record_claim(now).is_ok();
+    let claimed = if auto_claim {
+        // Take what the existing stake has accrued for payout; the full new
+        // balance accrues from now on.
+        let computed = calculate_reward(host, ctx, &params.owner)?;
+        let reward = clamp_reward(host.state_mut(), &params.owner, computed, logger)?;
+        // Reward the pool does not cover and the policy keeps owed stays on the
+        // position.
+        let (covered, owed) = host.state_mut().cover_reward(reward, now);
+        if let Some(mut owner_state) = host.state_mut().stake.get_mut(&params.owner) {
+            owner_state.accrued += owed;
+        }
+        Some(covered)
+    } else {
+        None
+    };
+    host.state_mut().insert_token(
+        &params.owner,
+        params.amount,
+        now,
+        None,
+    )?;
+    host.state_mut().credit_referral(&params.owner, params.referrer, params.amount)?;
+    log_event(host.state(), logger, &StakingEvent::Staked {
+        owner:  params.owner,
+        amount: params.amount,
+    })?;
+
+    if host.state().custody {
+        host.state_mut().held_principal += params.amount;
+        Cis2Client::transfer(
+            host,
+            TOKEN_ID,
+            params.token_contract_address,
+            concordium_cis2::TokenAmountU64(params.amount),
+            Address::Account(params.owner),
+            self_receiver(ctx),
+            AdditionalData::empty(),
+        )
+        .map_err(CustomContractError::Cis2ClientError)?;
+    }
+    if let Some(reward) = claimed {
+        pay_reward(host, ctx, params.token_contract_address, &params.owner, reward, now)?;
+        log_event(host.state(), logger, &StakingEvent::Claimed {
+            owner: params.owner,
+            reward,
+        })?;
+    }
+
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct StakeForParams {
+    owner:                  AccountAddress,
+    amount:                 u64,
+    token_contract_address: ContractAddress,
+}
+
+/// Stakes the sender's tokens on behalf of `owner`, for managed staking. The
+/// owner earns the reward, while the principal is returned to the sender as
+/// funder on unstake. Only available in custody mode, as the principal has to
+/// leave the funder's wallet.
+#[receive(
+    contract = "token-staking",
+    name = "stakeFor",
+    parameter = "StakeForParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn stake_for<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: StakeForParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    host.state().ensure_not_paused(PAUSE_STAKE)?;
+
+    ensure_not_self(ctx)?;
+    let funder = match ctx.sender() {
+        Address::Account(funder) => funder,
+        Address::Contract(_) => return Err(ContractError::Unauthorized),
+    };
+    ensure!(host.state().custody, CustomContractError::NotCustody.into());
+    stake_funded(host, ctx, logger, funder, &params)?;
+    host.state_mut().exit_call();
+
+    Ok(())
+}
+
+/// Stakes `funder`'s tokens for `params.owner`, as `stakeFor` does once the
+/// funder is known.
+fn stake_funded<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    ctx: &impl HasReceiveContext,
+    logger: &mut impl HasLogger,
+    funder: AccountAddress,
+    params: &StakeForParams,
+) -> ContractResult<()> {
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    ensure_supports_cis2(host, &params.token_contract_address)?;
+    ensure_balance(host, TOKEN_ID, &params.token_contract_address, params.amount, ctx)?;
+
+    let now = current_time(ctx);
+    let state = host.state_mut();
+    state.insert_token(&params.owner, params.amount, now, Some(funder))?;
+    state.held_principal += params.amount;
+    log_event(host.state(), logger, &StakingEvent::Staked {
+        owner:  params.owner,
+        amount: params.amount,
+    })?;
+    Cis2Client::transfer(
         host,
-        ctx.sender(),
-        ctx.self_address(),
-        token_contract_address,
+        TOKEN_ID,
+        params.token_contract_address,
+        concordium_cis2::TokenAmountU64(params.amount),
+        Address::Account(funder),
+        self_receiver(ctx),
+        AdditionalData::empty(),
     )
     .map_err(CustomContractError::Cis2ClientError)?;
-    ensure!(is_operator, CustomContractError::NotOperator);
+
     Ok(())
 }
 
-fn ensure_balance<S: HasStateApi>(
+/// Stakes the sender's tokens for several owners in one transaction, as a
+/// `stakeFor` per entry with the sender as funder. The batch is atomic: if
+/// any entry fails, e.g. the balance check, the whole transaction is
+/// rejected and no entry takes effect. Takes at most `MAX_BATCH_STAKE`
+/// entries, each for a different owner.
+#[receive(
+    contract = "token-staking",
+    name = "batchStake",
+    parameter = "Vec<StakeForParams>",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn batch_stake<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
     host: &mut impl HasHost<State<S>, StateApiType = S>,
-    token_id: ContractTokenId,
-    token_contract_address: &ContractAddress,
-    amount: u64,
-    ctx: &impl HasReceiveContext<()>,
-) -> Result<(), CustomContractError> {
-    let has_balance = Cis2Client::has_balance(host, token_id, token_contract_address, amount, ctx.sender())
-        .map_err(CustomContractError::Cis2ClientError)?;
-    ensure!(has_balance, CustomContractError::NoBalance);
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let entries: Vec<StakeForParams> = parse_batch(ctx)?;
+    host.state_mut().enter_call()?;
+    host.state().ensure_not_paused(PAUSE_STAKE)?;
+
+    ensure_not_self(ctx)?;
+    let funder = match ctx.sender() {
+        Address::Account(funder) => funder,
+        Address::Contract(_) => return Err(ContractError::Unauthorized),
+    };
+    ensure!(host.state().custody, CustomContractError::NotCustody.into());
+    for (i, params) in entries.iter().enumerate() {
+        ensure!(
+            entries[..i].iter().all(|earlier| earlier.owner != params.owner),
+            CustomContractError::DuplicateOwnerInBatch.into()
+        );
+    }
+    for params in &entries {
+        stake_funded(host, ctx, logger, funder, params)?;
+    }
+
+    host.state_mut().exit_call();
     Ok(())
 }
+
+#[derive(Serial, Deserial, SchemaType)]
+struct UnStakeParams {
+    owner:  AccountAddress,
+    token_contract_address: ContractAddress,
+    /// Principal to withdraw, at least one token. The rest of the position
+    /// stays staked.
+    amount: u64
+}
+
+#[receive(
+    contract = "token-staking",
+    name = "unstake",
+    parameter = "UnStakeParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn unstake_token<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: UnStakeParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    let sender = ctx.sender();
+    host.state().ensure_not_paused(PAUSE_UNSTAKE)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(sender.matches_account(&params.owner), ContractError::Unauthorized);
+    ensure!(host.state().cooldown_seconds == 0, CustomContractError::CoolingDown.into());
+    // Unstaking pays out reward, so an empty unstake would be a claim that
+    // bypasses the claim pause and throttle.
+    ensure!(params.amount > 0, CustomContractError::InvalidUnstakeAmount.into());
+
+    let now = current_time(ctx);
+    ensure!(
+        host.state().stake.get(&params.owner).is_none_or(|owner_state| now >= owner_state.lock_until),
+        CustomContractError::StillLocked.into()
+    );
+    host.state_mut().begin_operation(&params.owner, PositionStatus::Unstaking)?;
+    let (staked, principal_recipient, deposited_at) = host
+        .state()
+        .stake
+        .get(&params.owner)
+        .map_or((0, params.owner, now), |owner_state| {
+            (owner_state.amount, owner_state.funder.unwrap_or(params.owner), owner_state.deposited_at)
+        });
+    let principal = params.amount;
+    ensure!(principal <= staked, CustomContractError::NoBalance.into());
+    // Unstaking pays out the whole accrued reward. A remaining position goes
+    // on accruing from now.
+    let computed = calculate_reward(host, ctx, &params.owner)?;
+    host.state_mut().withdraw_token(&params.owner, principal)?;
+    host.state_mut().record_exit(&params.owner, deposited_at, now);
+    host.state_mut().record_interaction(&params.owner, now);
+
+    let reward = clamp_reward(host.state_mut(), &params.owner, computed, logger)?;
+    // Exiting must not depend on the reward pool: pay what the pool covers
+    // and leave the rest as residual reward.
+    let (covered, owed) = host.state_mut().cover_reward(reward, now);
+    record_shortfall(host.state_mut(), logger, &params.owner, owed, now)?;
+    let reward = covered;
+    // Installments take precedence over vesting for unstake reward.
+    let pay_now = host.state().installments.is_none();
+    if !pay_now {
+        host.state_mut().schedule_installments(&params.owner, reward, now);
+    }
+
+    // All effects on the position are applied; only transfers remain. The
+    // position stays `Unstaking` until they have completed.
+    if pay_now {
+        pay_reward(host, ctx, params.token_contract_address, &params.owner, reward, now)?;
+    }
+    if host.state().custody {
+        let fee = charge_unstake_fee(host, ctx, params.token_contract_address, &params.owner, principal, logger)?;
+        transfer_principal(host, ctx, params.token_contract_address, &principal_recipient, principal - fee)?;
+    }
+    log_event(host.state(), logger, &StakingEvent::Unstaked {
+        owner: params.owner,
+        amount: principal,
+        reward,
+    })?;
+
+    host.state_mut().end_operation(&params.owner);
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+/// Escape hatch returning the sender's staked principal while forfeiting all
+/// reward the position has accrued, left in the reward pool. It pays no
+/// reward, so it works however small the reward pool is. Pause flags do not
+/// block it, so that stakers can always exit, but the lock and the unstake
+/// fee still apply. Principal awaiting a cooldown stays there. With a
+/// cooldown the principal is not returned right away but moved into the
+/// cooldown, to be withdrawn with `withdraw` like after `requestUnstake`.
+#[receive(
+    contract = "token-staking",
+    name = "emergencyWithdraw",
+    parameter = "ContractAddress",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn emergency_withdraw<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let token_contract_address: ContractAddress = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &token_contract_address)?;
+    ensure_not_self(ctx)?;
+    let owner = match ctx.sender() {
+        Address::Account(owner) => owner,
+        Address::Contract(_) => bail!(ContractError::Unauthorized),
+    };
+
+    let now = current_time(ctx);
+    let state = host.state_mut();
+    let (principal, principal_recipient, lock_until, status) = state
+        .stake
+        .get(&owner)
+        .map(|owner_state| {
+            (owner_state.amount, owner_state.funder.unwrap_or(owner), owner_state.lock_until, owner_state.status)
+        })
+        .ok_or(CustomContractError::TokenNotFound)?;
+    ensure!(now >= lock_until, CustomContractError::StillLocked.into());
+    ensure!(status == PositionStatus::Active, CustomContractError::PositionBusy.into());
+    // Settle so that the reward forfeited below includes the unsettled part.
+    state.settle(&owner, now)?;
+    let cooldown = state.cooldown_seconds > 0;
+    if cooldown {
+        ensure!(principal > 0, CustomContractError::NoBalance.into());
+        state.request_unstake(&owner, principal, now)?;
+    }
+    let forfeited = state.stake.get(&owner).map_or(0, |owner_state| owner_state.accrued);
+    state.forfeit_reward(forfeited);
+    if let Some(mut owner_state) = state.stake.get_mut(&owner) {
+        owner_state.accrued = 0;
+        owner_state.last_claimed_at = now;
+        owner_state.last_interaction_at = now;
+    }
+    if cooldown {
+        host.state_mut().exit_call();
+        return Ok(());
+    }
+    state.update_pool(now);
+    state.withdraw_token(&owner, principal)?;
+
+    if host.state().custody {
+        let fee = charge_unstake_fee(host, ctx, token_contract_address, &owner, principal, logger)?;
+        transfer_principal(host, ctx, token_contract_address, &principal_recipient, principal - fee)?;
+    }
+    log_event(host.state(), logger, &StakingEvent::Unstaked {
+        owner,
+        amount: principal,
+        reward: 0,
+    })?;
+
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct RequestUnstakeParams {
+    owner:  AccountAddress,
+    /// Principal to unstake. The rest of the position stays staked.
+    amount: u64,
+}
+
+/// Starts the cooldown for unstaking `amount` of the sender's principal. The
+/// amount stops accruing reward now and can be withdrawn with `withdraw`
+/// once `cooldown_seconds` have passed since the latest request. Reward
+/// accrued so far stays claimable.
+#[receive(
+    contract = "token-staking",
+    name = "requestUnstake",
+    parameter = "RequestUnstakeParams",
+    error = "ContractError",
+    mutable
+)]
+fn request_unstake<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let params: RequestUnstakeParams = parse_parameter(ctx)?;
+    host.state().ensure_not_paused(PAUSE_UNSTAKE)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(ctx.sender().matches_account(&params.owner), ContractError::Unauthorized);
+    ensure!(params.amount > 0, CustomContractError::InvalidUnstakeAmount.into());
+
+    let now = current_time(ctx);
+    let state = host.state_mut();
+    let (lock_until, status, deposited_at) = state
+        .stake
+        .get(&params.owner)
+        .map(|owner_state| (owner_state.lock_until, owner_state.status, owner_state.deposited_at))
+        .ok_or(CustomContractError::TokenNotFound)?;
+    ensure!(now >= lock_until, CustomContractError::StillLocked.into());
+    ensure!(status == PositionStatus::Active, CustomContractError::PositionBusy.into());
+    state.request_unstake(&params.owner, params.amount, now)?;
+    state.record_exit(&params.owner, deposited_at, now);
+    state.record_interaction(&params.owner, now);
+
+    Ok(())
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct WithdrawParams {
+    owner:                  AccountAddress,
+    token_contract_address: ContractAddress,
+}
+
+/// Withdraws the principal the sender requested to unstake, once its
+/// cooldown has passed. Rejects with `CoolingDown` before that.
+#[receive(
+    contract = "token-staking",
+    name = "withdraw",
+    parameter = "WithdrawParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn withdraw<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: WithdrawParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    host.state().ensure_not_paused(PAUSE_UNSTAKE)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(ctx.sender().matches_account(&params.owner), ContractError::Unauthorized);
+
+    let now = current_time(ctx);
+    let state = host.state_mut();
+    let cooldown_millis = state.cooldown_seconds.saturating_mul(1000);
+    let mut owner_state = state.stake.get_mut(&params.owner).ok_or(CustomContractError::TokenNotFound)?;
+    ensure!(owner_state.status == PositionStatus::Active, CustomContractError::PositionBusy.into());
+    ensure!(owner_state.unbonding_amount > 0, CustomContractError::NoBalance.into());
+    ensure!(
+        now >= owner_state.unbonding_at.saturating_add(cooldown_millis),
+        CustomContractError::CoolingDown.into()
+    );
+    let principal = core::mem::take(&mut owner_state.unbonding_amount);
+    owner_state.last_interaction_at = now;
+    let principal_recipient = owner_state.funder.unwrap_or(params.owner);
+    let closed = owner_state.amount == 0 && owner_state.accrued == 0 && owner_state.adjustment == 0;
+    drop(owner_state);
+    if closed {
+        state.remove_staker(&params.owner)?;
+    }
+
+    if host.state().custody {
+        let fee = charge_unstake_fee(host, ctx, params.token_contract_address, &params.owner, principal, logger)?;
+        transfer_principal(host, ctx, params.token_contract_address, &principal_recipient, principal - fee)?;
+    }
+    log_event(host.state(), logger, &StakingEvent::Unstaked {
+        owner:  params.owner,
+        amount: principal,
+        reward: 0,
+    })?;
+
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct StakeNewLotParams {
+    owner:                  AccountAddress,
+    amount:                 u64,
+    /// Seconds from now during which the lot cannot be unstaked. Lots are
+    /// locked for at least the minimum lock of a stake.
+    lock_seconds:           u64,
+    token_contract_address: ContractAddress,
+}
+
+/// Stakes `amount` as a new lot, independent of the owner's other lots and
+/// of their `stake` position, so that each deposit can carry its own lock.
+/// Returns the id of the new lot.
+#[receive(
+    contract = "token-staking",
+    name = "stakeNewLot",
+    parameter = "StakeNewLotParams",
+    return_value = "u64",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn stake_new_lot<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<u64> {
+    let params: StakeNewLotParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    host.state().ensure_not_paused(PAUSE_STAKE)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(ctx.sender().matches_account(&params.owner), ContractError::Unauthorized);
+    ensure_supports_cis2(host, &params.token_contract_address)?;
+    ensure_balance(host, TOKEN_ID, &params.token_contract_address, params.amount, ctx)?;
+    if !host.state().custody {
+        ensure_is_operator(host, ctx, &params.token_contract_address)?;
+    }
+
+    let now = current_time(ctx);
+    let (state, state_builder) = host.state_and_builder();
+    // Every lot is a deposit of its own, so each must meet the minimum.
+    ensure!(params.amount >= state.min_stake_amount, CustomContractError::BelowMinimumStake.into());
+    state.ensure_within_cap(&params.owner, params.amount)?;
+    // A lot is locked for at least as long as a stake made now.
+    let lock_until = now.saturating_add(params.lock_seconds.saturating_mul(1000)).max(state.lock_until(now));
+    let opens_account = state.account_principal(&params.owner) == 0;
+    state.update_pool(now);
+    let lot_id = state.next_lot_id;
+    state.next_lot_id += 1;
+    let _ = state.lots.entry(params.owner).or_insert_with(|| state_builder.new_map()).insert(
+        lot_id,
+        StakeState {
+            amount:                params.amount,
+            staked_start_at:       now,
+            status:                PositionStatus::Active,
+            funder:                None,
+            accrued:               0u64,
+            lock_until,
+            adjustment:            0i64,
+            reward_per_token_paid: state.reward_per_token,
+            deposited_at:          now,
+            unbonding_amount:      0u64,
+            unbonding_at:          0u64,
+            last_claimed_at:       now,
+            last_interaction_at:   now,
+        },
+    );
+    let total_before = state.total_staked;
+    state.total_staked =
+        state.total_staked.checked_add(params.amount).ok_or(CustomContractError::AmountOverflow)?;
+    if opens_account && params.amount > 0 {
+        state.staker_count += 1;
+    }
+    ensure_total_delta(total_before, state.total_staked, 0, params.amount)?;
+    log_event(host.state(), logger, &StakingEvent::Staked {
+        owner:  params.owner,
+        amount: params.amount,
+    })?;
+
+    if host.state().custody {
+        host.state_mut().held_principal += params.amount;
+        Cis2Client::transfer(
+            host,
+            TOKEN_ID,
+            params.token_contract_address,
+            concordium_cis2::TokenAmountU64(params.amount),
+            Address::Account(params.owner),
+            self_receiver(ctx),
+            AdditionalData::empty(),
+        )
+        .map_err(CustomContractError::Cis2ClientError)?;
+    }
+
+    host.state_mut().exit_call();
+    Ok(lot_id)
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct UnstakeLotParams {
+    owner:                  AccountAddress,
+    lot_id:                 u64,
+    token_contract_address: ContractAddress,
+}
+
+/// Unstakes a single lot once its lock has passed, paying out the reward it
+/// accrued and, in custody mode, returning its principal less the unstake
+/// fee. With a cooldown the principal stops accruing and is only returned by
+/// `withdrawLot` once `cooldown_seconds` have passed, like `requestUnstake`.
+#[receive(
+    contract = "token-staking",
+    name = "unstakeLot",
+    parameter = "UnstakeLotParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn unstake_lot<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: UnstakeLotParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    host.state().ensure_not_paused(PAUSE_UNSTAKE)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(ctx.sender().matches_account(&params.owner), ContractError::Unauthorized);
+
+    let now = current_time(ctx);
+    let state = host.state_mut();
+    let cooldown = state.cooldown_seconds > 0;
+    let lot = state
+        .lots
+        .get(&params.owner)
+        .and_then(|owner_lots| owner_lots.get(&params.lot_id).map(|lot| lot.clone()))
+        .ok_or(CustomContractError::TokenNotFound)?;
+    ensure!(lot.unbonding_amount == 0, CustomContractError::CoolingDown.into());
+    ensure!(now >= lot.lock_until, CustomContractError::StillLocked.into());
+    if let Some(mut owner_lots) = state.lots.get_mut(&params.owner) {
+        if cooldown {
+            if let Some(mut unbonding) = (*owner_lots).get_mut(&params.lot_id) {
+                unbonding.amount = 0;
+                unbonding.accrued = 0;
+                unbonding.unbonding_amount = lot.amount;
+                unbonding.unbonding_at = now;
+                unbonding.last_claimed_at = now;
+            }
+        } else {
+            owner_lots.remove(&params.lot_id);
+        }
+    }
+    state.update_pool(now);
+    let (reward, remainder) = state.position_reward(&lot, now);
+    let released = state.unsettled_of(&lot, now);
+    state.release_unsettled(released);
+    let total_before = state.total_staked;
+    state.total_staked =
+        state.total_staked.checked_sub(lot.amount).ok_or(CustomContractError::AmountOverflow)?;
+    if lot.amount > 0 && state.account_principal(&params.owner) == 0 {
+        state.staker_count -= 1;
+    }
+    ensure_total_delta(total_before, state.total_staked, lot.amount, 0)?;
+    let computed = lot.accrued.saturating_add(reward);
+    state.rounding_loss += remainder;
+    state.total_liabilities += u128::from(reward);
+    state.record_exit(&params.owner, lot.deposited_at, now);
+
+    let reward = clamp_reward(host.state_mut(), &params.owner, computed, logger)?;
+    // Exiting must not depend on the reward pool: pay what the pool covers
+    // and leave the rest as residual reward.
+    let (covered, owed) = host.state_mut().cover_reward(reward, now);
+    record_shortfall(host.state_mut(), logger, &params.owner, owed, now)?;
+    let reward = covered;
+
+    // All effects on the lot are applied; only transfers remain.
+    pay_reward(host, ctx, params.token_contract_address, &params.owner, reward, now)?;
+    if cooldown {
+        log_event(host.state(), logger, &StakingEvent::Claimed {
+            owner: params.owner,
+            reward,
+        })?;
+    } else {
+        if host.state().custody {
+            let fee =
+                charge_unstake_fee(host, ctx, params.token_contract_address, &params.owner, lot.amount, logger)?;
+            transfer_principal(host, ctx, params.token_contract_address, &params.owner, lot.amount - fee)?;
+        }
+        log_event(host.state(), logger, &StakingEvent::Unstaked {
+            owner: params.owner,
+            amount: lot.amount,
+            reward,
+        })?;
+    }
+
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+/// Withdraws the principal of a lot unstaked with `unstakeLot`, once its
+/// cooldown has passed, and removes the lot. Rejects with `CoolingDown`
+/// before that.
+#[receive(
+    contract = "token-staking",
+    name = "withdrawLot",
+    parameter = "UnstakeLotParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn withdraw_lot<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: UnstakeLotParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    host.state().ensure_not_paused(PAUSE_UNSTAKE)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(ctx.sender().matches_account(&params.owner), ContractError::Unauthorized);
+
+    let now = current_time(ctx);
+    let state = host.state_mut();
+    let cooldown_millis = state.cooldown_seconds.saturating_mul(1000);
+    let (principal, unbonding_at) = state
+        .lots
+        .get(&params.owner)
+        .and_then(|owner_lots| owner_lots.get(&params.lot_id).map(|lot| (lot.unbonding_amount, lot.unbonding_at)))
+        .ok_or(CustomContractError::TokenNotFound)?;
+    ensure!(principal > 0, CustomContractError::NoBalance.into());
+    ensure!(now >= unbonding_at.saturating_add(cooldown_millis), CustomContractError::CoolingDown.into());
+    if let Some(mut owner_lots) = state.lots.get_mut(&params.owner) {
+        owner_lots.remove(&params.lot_id);
+    }
+
+    if host.state().custody {
+        let fee = charge_unstake_fee(host, ctx, params.token_contract_address, &params.owner, principal, logger)?;
+        transfer_principal(host, ctx, params.token_contract_address, &params.owner, principal - fee)?;
+    }
+    log_event(host.state(), logger, &StakingEvent::Unstaked {
+        owner:  params.owner,
+        amount: principal,
+        reward: 0,
+    })?;
+
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+#[derive(Serialize, SchemaType)]
+struct LotView {
+    lot_id:           u64,
+    amount:           u64,
+    staked_start_at:  u64,
+    lock_until:       u64,
+    /// Principal awaiting `withdrawLot` after the cooldown.
+    unbonding_amount: u64,
+}
+
+/// Lists the lots staked by an account.
+#[receive(
+    contract = "token-staking",
+    name = "viewLots",
+    parameter = "AccountAddress",
+    return_value = "Vec<LotView>",
+    error = "ContractError"
+)]
+fn view_lots<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Vec<LotView>> {
+    let owner: AccountAddress = parse_parameter(ctx)?;
+    let lots = match host.state().lots.get(&owner) {
+        Some(owner_lots) => owner_lots
+            .iter()
+            .map(|(lot_id, lot)| LotView {
+                lot_id:           *lot_id,
+                amount:           lot.amount,
+                staked_start_at:  lot.staked_start_at,
+                lock_until:       lot.lock_until,
+                unbonding_amount: lot.unbonding_amount,
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(lots)
+}
+
+/// Stakes the CCD sent along with the call. Adding to an existing CCD
+/// position settles its accrued reward first and extends its lock to
+/// `min_lock_seconds` from now. The minimum stake and the cap apply to the
+/// position's value under `tokens_per_ccd`.
+#[receive(
+    contract = "token-staking",
+    name = "stakeCcd",
+    error = "ContractError",
+    payable,
+    mutable,
+    enable_logger
+)]
+fn stake_ccd<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    amount: Amount,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    host.state().ensure_not_paused(PAUSE_STAKE)?;
+    ensure!(host.state().tokens_per_ccd.is_some(), CustomContractError::CcdStakingDisabled.into());
+    ensure_not_self(ctx)?;
+    let owner = match ctx.sender() {
+        Address::Account(account) => account,
+        Address::Contract(_) => bail!(ContractError::Unauthorized),
+    };
+
+    let now = current_time(ctx);
+    let state = host.state_mut();
+    let (amount_before, start, accrued) = state
+        .ccd_stake
+        .get(&owner)
+        .map_or((0, now, 0), |position| (position.amount, position.last_claimed_at, position.accrued));
+    let amount_after =
+        amount_before.checked_add(amount.micro_ccd).ok_or(CustomContractError::AmountOverflow)?;
+    ensure!(
+        amount_before > 0 || state.ccd_value(amount.micro_ccd) >= state.min_stake_amount,
+        CustomContractError::BelowMinimumStake.into()
+    );
+    ensure!(
+        state.max_stake_per_account.is_none_or(|cap| state.ccd_value(amount_after) <= cap),
+        CustomContractError::StakeCapExceeded.into()
+    );
+    state.update_pool(now);
+    let (reward, remainder) = state.reward_between(state.ccd_value(amount_before), start, now);
+    let released = state.reward_weighted(state.ccd_value(amount_before), start, now, None);
+    state.release_unsettled(released);
+    state.rounding_loss += remainder;
+    state.total_liabilities += u128::from(reward);
+    state.total_ccd_staked =
+        state.total_ccd_staked.checked_add(amount.micro_ccd).ok_or(CustomContractError::AmountOverflow)?;
+    let lock_until = state.lock_until(now);
+    let mut position = state.ccd_stake.entry(owner).or_insert_with(StakeState::empty);
+    position.amount = amount_after;
+    position.lock_until = position.lock_until.max(lock_until);
+    position.accrued = accrued.saturating_add(reward);
+    if amount_before == 0 {
+        position.staked_start_at = now;
+    }
+    position.last_claimed_at = now;
+    position.deposited_at = now;
+    drop(position);
+    log_event(host.state(), logger, &StakingEvent::CcdStaked {
+        owner,
+        amount: amount.micro_ccd,
+    })?;
+
+    Ok(())
+}
+
+/// Unstakes the sender's CCD position once its lock has passed, returning
+/// the CCD and paying out the token reward it accrued. With a cooldown the
+/// CCD stops accruing and is only returned by `withdrawCcd` once
+/// `cooldown_seconds` have passed, like `requestUnstake`.
+#[receive(
+    contract = "token-staking",
+    name = "unstakeCcd",
+    parameter = "ContractAddress",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn unstake_ccd<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let token_contract_address: ContractAddress = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &token_contract_address)?;
+    host.state().ensure_not_paused(PAUSE_UNSTAKE)?;
+    ensure_not_self(ctx)?;
+    let owner = match ctx.sender() {
+        Address::Account(account) => account,
+        Address::Contract(_) => bail!(ContractError::Unauthorized),
+    };
+
+    let now = current_time(ctx);
+    let state = host.state_mut();
+    let cooldown = state.cooldown_seconds > 0;
+    let position =
+        state.ccd_stake.get(&owner).map(|position| position.clone()).ok_or(CustomContractError::TokenNotFound)?;
+    ensure!(position.unbonding_amount == 0, CustomContractError::CoolingDown.into());
+    ensure!(now >= position.lock_until, CustomContractError::StillLocked.into());
+    if cooldown {
+        if let Some(mut unbonding) = state.ccd_stake.get_mut(&owner) {
+            unbonding.amount = 0;
+            unbonding.accrued = 0;
+            unbonding.unbonding_amount = position.amount;
+            unbonding.unbonding_at = now;
+            unbonding.last_claimed_at = now;
+        }
+    } else {
+        state.ccd_stake.remove(&owner);
+    }
+    state.update_pool(now);
+    let released = state.reward_weighted(state.ccd_value(position.amount), position.last_claimed_at, now, None);
+    state.release_unsettled(released);
+    state.total_ccd_staked =
+        state.total_ccd_staked.checked_sub(position.amount).ok_or(CustomContractError::AmountOverflow)?;
+    let (reward, remainder) = state.ccd_reward(&position, now);
+    state.rounding_loss += remainder;
+    state.total_liabilities += u128::from(reward);
+    let computed = position.accrued.saturating_add(reward);
+
+    let reward = clamp_reward(host.state_mut(), &owner, computed, logger)?;
+    let (covered, owed) = host.state_mut().cover_reward(reward, now);
+    record_shortfall(host.state_mut(), logger, &owner, owed, now)?;
+    let reward = covered;
+    pay_reward(host, ctx, token_contract_address, &owner, reward, now)?;
+    if cooldown {
+        log_event(host.state(), logger, &StakingEvent::Claimed {
+            owner,
+            reward,
+        })?;
+    } else {
+        host.invoke_transfer(&owner, Amount::from_micro_ccd(position.amount))
+            .map_err(|_| CustomContractError::InvokeContractError)?;
+        log_event(host.state(), logger, &StakingEvent::CcdUnstaked {
+            owner,
+            amount: position.amount,
+            reward,
+        })?;
+    }
+
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+/// Returns the CCD unstaked with `unstakeCcd` once its cooldown has passed.
+/// Rejects with `CoolingDown` before that.
+#[receive(
+    contract = "token-staking",
+    name = "withdrawCcd",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn withdraw_ccd<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    host.state_mut().enter_call()?;
+    host.state().ensure_not_paused(PAUSE_UNSTAKE)?;
+    ensure_not_self(ctx)?;
+    let owner = match ctx.sender() {
+        Address::Account(account) => account,
+        Address::Contract(_) => bail!(ContractError::Unauthorized),
+    };
+
+    let now = current_time(ctx);
+    let state = host.state_mut();
+    let cooldown_millis = state.cooldown_seconds.saturating_mul(1000);
+    let (principal, unbonding_at, restaked) = state
+        .ccd_stake
+        .get(&owner)
+        .map(|position| (position.unbonding_amount, position.unbonding_at, position.amount > 0))
+        .ok_or(CustomContractError::TokenNotFound)?;
+    ensure!(principal > 0, CustomContractError::NoBalance.into());
+    ensure!(now >= unbonding_at.saturating_add(cooldown_millis), CustomContractError::CoolingDown.into());
+    // CCD staked again since the unstake keeps its position.
+    if restaked {
+        if let Some(mut position) = state.ccd_stake.get_mut(&owner) {
+            position.unbonding_amount = 0;
+        }
+    } else {
+        state.ccd_stake.remove(&owner);
+    }
+
+    host.invoke_transfer(&owner, Amount::from_micro_ccd(principal))
+        .map_err(|_| CustomContractError::InvokeContractError)?;
+    log_event(host.state(), logger, &StakingEvent::CcdUnstaked {
+        owner,
+        amount: principal,
+        reward: 0,
+    })?;
+
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct ClaimParams {
+    owner:  AccountAddress,
+    token_contract_address: ContractAddress
+}
+
+/// Pays out the reward accrued by the owner's position and restarts its
+/// accrual from now. The principal stays staked.
+///
+/// Interaction with pausing and freezing:
+/// - `PAUSE_CLAIM` set: rejected with `ContractPaused`; accrual continues.
+/// - Within or after a no-accrual window: succeeds, paying nothing for the
+///   time covered by the window.
+/// - `PAUSE_STAKE` or `PAUSE_UNSTAKE` set: succeeds as usual.
+#[receive(
+    contract = "token-staking",
+    name = "claim",
+    parameter = "ClaimParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn claim_reward<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: ClaimParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    let sender = ctx.sender();
+    host.state().ensure_not_paused(PAUSE_CLAIM)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(sender.matches_account(&params.owner), ContractError::Unauthorized);
+
+    let now = current_time(ctx);
+    host.state_mut().record_claim(now)?;
+    host.state_mut().begin_operation(&params.owner, PositionStatus::Claiming)?;
+
+    let computed = calculate_reward(host, ctx, &params.owner)?;
+    let reward = clamp_reward(host.state_mut(), &params.owner, computed, logger)?;
+    // Reward the pool does not cover and the policy keeps owed stays on the
+    // position.
+    let (covered, owed) = host.state_mut().cover_reward(reward, now);
+    if let Some(mut owner_state) = host.state_mut().stake.get_mut(&params.owner) {
+        owner_state.accrued += owed;
+        owner_state.last_interaction_at = now;
+    }
+    let reward = covered;
+    // A position kept open only for reward owed to it closes once that is
+    // paid out.
+    let closed = host.state().stake.get(&params.owner).is_some_and(|owner_state| {
+        owner_state.amount == 0
+            && owner_state.unbonding_amount == 0
+            && owner_state.accrued == 0
+            && owner_state.adjustment == 0
+    });
+    if closed {
+        host.state_mut().remove_staker(&params.owner)?;
+    }
+
+    // All effects on the position are applied; only the payout remains.
+    // The position stays `Claiming` until it has completed.
+    pay_reward(host, ctx, params.token_contract_address, &params.owner, reward, now)?;
+    log_event(host.state(), logger, &StakingEvent::Claimed {
+        owner: params.owner,
+        reward,
+    })?;
+
+    host.state_mut().end_operation(&params.owner);
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+/// Pays out the referral bonus credited to the sender.
+#[receive(
+    contract = "token-staking",
+    name = "claimReferralReward",
+    parameter = "ContractAddress",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn claim_referral_reward<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let token_contract_address: ContractAddress = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &token_contract_address)?;
+    host.state().ensure_not_paused(PAUSE_CLAIM)?;
+
+    ensure_not_self(ctx)?;
+    let referrer = match ctx.sender() {
+        Address::Account(account) => account,
+        Address::Contract(_) => bail!(ContractError::Unauthorized),
+    };
+
+    let now = current_time(ctx);
+    host.state_mut().record_claim(now)?;
+    let reward = host
+        .state_mut()
+        .referral_rewards
+        .remove_and_get(&referrer)
+        .ok_or(CustomContractError::NoReferralReward)?;
+
+    transfer_reward(host, ctx, token_contract_address, reward)?;
+    log_event(host.state(), logger, &StakingEvent::Claimed {
+        owner: referrer,
+        reward,
+    })?;
+    host.state_mut().exit_call();
+
+    Ok(())
+}
+
+/// Pays out every installment of the owner's unstake reward that is due.
+#[receive(
+    contract = "token-staking",
+    name = "claimInstallment",
+    parameter = "ClaimParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn claim_installment<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: ClaimParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    host.state().ensure_not_paused(PAUSE_CLAIM)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(ctx.sender().matches_account(&params.owner), ContractError::Unauthorized);
+
+    let now = current_time(ctx);
+    host.state_mut().record_claim(now)?;
+    let state = host.state_mut();
+    let mut installments = state
+        .pending_installments
+        .remove_and_get(&params.owner)
+        .ok_or(CustomContractError::NoInstallmentDue)?;
+    let due: u64 = installments
+        .iter()
+        .filter(|installment| installment.release_at <= now)
+        .map(|installment| installment.amount)
+        .sum();
+    ensure!(due > 0, CustomContractError::NoInstallmentDue.into());
+    installments.retain(|installment| installment.release_at > now);
+    if !installments.is_empty() {
+        let _ = state.pending_installments.insert(params.owner, installments);
+    }
+
+    transfer_reward(host, ctx, params.token_contract_address, due)?;
+    log_event(host.state(), logger, &StakingEvent::Claimed {
+        owner:  params.owner,
+        reward: due,
+    })?;
+    host.state_mut().exit_call();
+
+    Ok(())
+}
+
+/// Pays out the part of the owner's claimed reward that has vested so far.
+#[receive(
+    contract = "token-staking",
+    name = "claimVested",
+    parameter = "ClaimParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn claim_vested<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: ClaimParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    host.state().ensure_not_paused(PAUSE_CLAIM)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(ctx.sender().matches_account(&params.owner), ContractError::Unauthorized);
+
+    let now = current_time(ctx);
+    host.state_mut().record_claim(now)?;
+    let state = host.state_mut();
+    let mut entries = state
+        .vesting_entries
+        .remove_and_get(&params.owner)
+        .ok_or(CustomContractError::NothingVested)?;
+    let mut releasable = 0u64;
+    for entry in entries.iter_mut() {
+        let vested = state.vested_amount(entry, now);
+        releasable += vested - entry.released;
+        entry.released = vested;
+    }
+    ensure!(releasable > 0, CustomContractError::NothingVested.into());
+    entries.retain(|entry| entry.released < entry.total);
+    if !entries.is_empty() {
+        let _ = state.vesting_entries.insert(params.owner, entries);
+    }
+
+    transfer_reward(host, ctx, params.token_contract_address, releasable)?;
+    log_event(host.state(), logger, &StakingEvent::Claimed {
+        owner:  params.owner,
+        reward: releasable,
+    })?;
+    host.state_mut().exit_call();
+
+    Ok(())
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct SplitRewardParams {
+    owner:                  AccountAddress,
+    /// Part of the accrued reward to add to the staked principal.
+    compound_amount:        u64,
+    token_contract_address: ContractAddress,
+}
+
+/// Compounds `compound_amount` of the accrued reward into the principal and
+/// pays out the rest. The position keeps accruing on the new principal from
+/// now on. Only available in custody mode, where the contract holds both.
+#[receive(
+    contract = "token-staking",
+    name = "splitReward",
+    parameter = "SplitRewardParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn split_reward<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: SplitRewardParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    let sender = ctx.sender();
+    host.state().ensure_not_paused(PAUSE_CLAIM)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(sender.matches_account(&params.owner), ContractError::Unauthorized);
+    ensure!(host.state().custody, CustomContractError::NotCustody.into());
+
+    let now = current_time(ctx);
+    host.state_mut().record_claim(now)?;
+    host.state_mut().begin_operation(&params.owner, PositionStatus::Claiming)?;
+
+    // The cap on a single payout applies to the whole reward, compounded or
+    // paid out.
+    let computed = calculate_reward(host, ctx, &params.owner)?;
+    let reward = clamp_reward(host.state_mut(), &params.owner, computed, logger)?;
+    ensure!(params.compound_amount <= reward, CustomContractError::CompoundExceedsReward.into());
+    // Compounded reward belongs to the owner, so it cannot join principal
+    // that is returned to a funder.
+    ensure!(
+        params.compound_amount == 0
+            || host.state().stake.get(&params.owner).is_some_and(|owner_state| owner_state.funder.is_none()),
+        CustomContractError::FunderMismatch.into()
+    );
+
+    let state = host.state_mut();
+    state.reward_pool = state
+        .reward_pool
+        .checked_sub(params.compound_amount)
+        .ok_or(CustomContractError::InsufficientRewardPool)?;
+    state.forfeit_reward(params.compound_amount);
+    state.held_principal += params.compound_amount;
+    // `calculate_reward` settled the position at the current slot time, so
+    // the compounded amount starts accruing from here.
+    state.add_principal(&params.owner, params.compound_amount)?;
+    state.record_interaction(&params.owner, now);
+    log_event(state, logger, &StakingEvent::Compounded {
+        owner:  params.owner,
+        amount: params.compound_amount,
+    })?;
+
+    // Reward the pool does not cover and the policy keeps owed stays on the
+    // position.
+    let (payout, owed) = host.state_mut().cover_reward(reward - params.compound_amount, now);
+    if let Some(mut owner_state) = host.state_mut().stake.get_mut(&params.owner) {
+        owner_state.accrued += owed;
+    }
+    pay_reward(host, ctx, params.token_contract_address, &params.owner, payout, now)?;
+    log_event(host.state(), logger, &StakingEvent::Claimed {
+        owner:  params.owner,
+        reward: payout,
+    })?;
+    host.state_mut().end_operation(&params.owner);
+
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+/// Pays out residual reward left over from a full unstake. Once the claim
+/// window has passed the residual is forfeited and stays in the reward pool.
+#[receive(
+    contract = "token-staking",
+    name = "claimResidual",
+    parameter = "ClaimParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn claim_residual<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: ClaimParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    let sender = ctx.sender();
+    host.state().ensure_not_paused(PAUSE_CLAIM)?;
+
+    ensure_not_self(ctx)?;
+    ensure!(sender.matches_account(&params.owner), ContractError::Unauthorized);
+
+    let now = current_time(ctx);
+    host.state_mut().record_claim(now)?;
+    let (amount, expires_at) = host
+        .state()
+        .pending_after_unstake
+        .get(&params.owner)
+        .map(|residual| (residual.amount, residual.expires_at))
+        .ok_or(CustomContractError::NoResidualReward)?;
+    ensure!(now < expires_at, CustomContractError::ResidualExpired.into());
+
+    host.state_mut().pending_after_unstake.remove(&params.owner);
+    let reward = clamp_reward(host.state_mut(), &params.owner, amount, logger)?;
+    pay_reward(host, ctx, params.token_contract_address, &params.owner, reward, now)?;
+
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+/// Sets which staker entrypoints are paused, as a bitfield of `PAUSE_*`
+/// flags. This allows e.g. halting new deposits while keeping withdrawals
+/// open.
+#[receive(
+    contract = "token-staking",
+    name = "setPauseFlags",
+    parameter = "u8",
+    error = "ContractError",
+    mutable
+)]
+fn set_pause_flags<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let pause_flags: u8 = parse_parameter(ctx)?;
+    let state = host.state_mut();
+
+    ensure!(ctx.sender().matches_account(&state.admin), ContractError::Unauthorized);
+    state.pause_flags = pause_flags;
+
+    Ok(())
+}
+
+/// Kill switch over the pause flags: `true` pauses staking and claiming,
+/// `false` unpauses everything. Unstaking stays open while paused, so that
+/// stakers can always exit; use `setPauseFlags` to halt it too.
+#[receive(
+    contract = "token-staking",
+    name = "setPaused",
+    parameter = "bool",
+    error = "ContractError",
+    mutable
+)]
+fn set_paused<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let paused: bool = parse_parameter(ctx)?;
+    let state = host.state_mut();
+
+    ensure!(ctx.sender().matches_account(&state.admin), ContractError::Unauthorized);
+    state.pause_flags = if paused { PAUSE_STAKE | PAUSE_CLAIM } else { 0 };
+
+    Ok(())
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct ApplyPenaltyParams {
+    owner:  AccountAddress,
+    /// Reward to withhold from the owner's future payouts.
+    amount: u64,
+}
+
+/// Penalizes a staker by reducing the reward they accrued but have not yet
+/// claimed. The principal is never touched.
+#[receive(
+    contract = "token-staking",
+    name = "applyPenalty",
+    parameter = "ApplyPenaltyParams",
+    error = "ContractError",
+    mutable
+)]
+fn apply_penalty<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let params: ApplyPenaltyParams = parse_parameter(ctx)?;
+    let state = host.state_mut();
+
+    ensure!(ctx.sender().matches_account(&state.admin), ContractError::Unauthorized);
+    let mut owner_state = state.stake.get_mut(&params.owner).ok_or(CustomContractError::TokenNotFound)?;
+    let penalty = params.amount.min(i64::MAX as u64) as i64;
+    owner_state.adjustment = owner_state.adjustment.saturating_sub(penalty);
+
+    Ok(())
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct ReclaimUnclaimedParams {
+    owner: AccountAddress,
+}
+
+/// Returns the reward accrued by a position that has been inactive for longer
+/// than the reclaim threshold back to the reward pool. The principal stays
+/// staked and starts accruing afresh.
+#[receive(
+    contract = "token-staking",
+    name = "reclaimUnclaimedAfter",
+    parameter = "ReclaimUnclaimedParams",
+    error = "ContractError",
+    mutable,
+    enable_logger
+)]
+fn reclaim_unclaimed_after<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    logger: &mut impl HasLogger,
+) -> ContractResult<()> {
+    let params: ReclaimUnclaimedParams = parse_parameter(ctx)?;
+    let now = current_time(ctx);
+    let state = host.state_mut();
+
+    ensure!(ctx.sender().matches_account(&state.admin), ContractError::Unauthorized);
+    let last_interaction = state
+        .stake
+        .get(&params.owner)
+        .map(|owner_state| owner_state.last_interaction_at)
+        .ok_or(CustomContractError::TokenNotFound)?;
+    ensure!(
+        state.reclaim_after.is_some_and(|threshold| {
+            now.saturating_sub(last_interaction) > threshold.saturating_mul(1000)
+        }),
+        CustomContractError::NotInactive.into()
+    );
+
+    // The reward was never debited from the pool, so dropping it from the
+    // ledger leaves it there.
+    let amount = state.take_reward(&params.owner, now)?;
+    state.forfeit_reward(amount);
+    log_event(state, logger, &StakingEvent::RewardReclaimed {
+        owner: params.owner,
+        amount,
+    })?;
+
+    Ok(())
+}
+
+/// Sets the number of seconds new stakes are locked for. Existing positions
+/// keep the lock they were staked under.
+#[receive(
+    contract = "token-staking",
+    name = "setMinLock",
+    parameter = "u64",
+    error = "ContractError",
+    mutable
+)]
+fn set_min_lock<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let min_lock_seconds: u64 = parse_parameter(ctx)?;
+    let state = host.state_mut();
+
+    ensure!(ctx.sender().matches_account(&state.admin), ContractError::Unauthorized);
+    state.min_lock_seconds = min_lock_seconds;
+
+    Ok(())
+}
+
+/// Sets the reward rate in basis points per `reward_period`. The old rate is
+/// kept for reward accrued so far, so the new rate only applies from now on,
+/// without settling any position.
+#[receive(
+    contract = "token-staking",
+    name = "setRewardRate",
+    parameter = "u16",
+    error = "ContractError",
+    mutable
+)]
+fn set_reward_rate<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let reward_rate_bps: u16 = parse_parameter(ctx)?;
+    let state = host.state_mut();
+
+    ensure!(ctx.sender().matches_account(&state.admin), ContractError::Unauthorized);
+    let season = state.season;
+    state.change_terms(current_time(ctx), reward_rate_bps, season);
+
+    Ok(())
+}
+
+/// Settles the accrued reward of up to `MAX_SETTLE_BATCH` stake positions
+/// at the current time, e.g. before a migration. Takes the number of
+/// positions to skip and returns where to continue, or `None` once all are
+/// settled. Settling again at the same time changes nothing.
+#[receive(
+    contract = "token-staking",
+    name = "forceSettleAll",
+    parameter = "u64",
+    return_value = "Option<u64>",
+    error = "ContractError",
+    mutable
+)]
+fn force_settle_all<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Option<u64>> {
+    let skip: u64 = parse_parameter(ctx)?;
+    let state = host.state_mut();
+
+    ensure!(ctx.sender().matches_account(&state.admin), ContractError::Unauthorized);
+    let now = current_time(ctx);
+    let owners: Vec<AccountAddress> =
+        state.stake.iter().skip(skip as usize).take(MAX_SETTLE_BATCH + 1).map(|(owner, _)| *owner).collect();
+    for owner in owners.iter().take(MAX_SETTLE_BATCH) {
+        state.settle(owner, now)?;
+    }
+
+    Ok(if owners.len() > MAX_SETTLE_BATCH { Some(skip + MAX_SETTLE_BATCH as u64) } else { None })
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct StartNewSeasonParams {
+    season:     Season,
+    /// Keep existing positions staked into the new season. Otherwise no
+    /// position may be outstanding.
+    carry_over: bool,
+}
+
+/// Replaces the staking season. The previous season is kept for reward
+/// accrued in it, so positions carried over keep that reward without being
+/// settled.
+#[receive(
+    contract = "token-staking",
+    name = "startNewSeason",
+    parameter = "StartNewSeasonParams",
+    error = "ContractError",
+    mutable
+)]
+fn start_new_season<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let params: StartNewSeasonParams = parse_parameter(ctx)?;
+    let state = host.state_mut();
+
+    ensure!(ctx.sender().matches_account(&state.admin), ContractError::Unauthorized);
+    ensure!(params.season.start < params.season.end, CustomContractError::InvalidSeason.into());
+    ensure!(params.carry_over || state.total_staked == 0, CustomContractError::PositionsOutstanding.into());
+
+    let rate_bps = state.reward_rate_bps;
+    state.change_terms(current_time(ctx), rate_bps, Some(params.season));
+
+    Ok(())
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct OfferPositionParams {
+    to:         AccountAddress,
+    /// Time in milliseconds after which the offer can no longer be accepted.
+    expires_at: u64,
+}
+
+/// Offers the sender's position to another account. The transfer only takes
+/// effect once the recipient accepts it with `acceptPosition`, so a position
+/// cannot be sent to a wrong or inaccessible address by mistake. A new offer
+/// replaces any previous one.
+#[receive(
+    contract = "token-staking",
+    name = "offerPosition",
+    parameter = "OfferPositionParams",
+    error = "ContractError",
+    mutable
+)]
+fn offer_position<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let params: OfferPositionParams = parse_parameter(ctx)?;
+    ensure_not_self(ctx)?;
+    let owner = match ctx.sender() {
+        Address::Account(owner) => owner,
+        Address::Contract(_) => return Err(ContractError::Unauthorized),
+    };
+    let state = host.state_mut();
+
+    ensure!(state.custody, CustomContractError::NotCustody.into());
+    ensure!(
+        state.stake.get(&owner).is_some_and(|owner_state| owner_state.amount > 0),
+        CustomContractError::TokenNotFound.into()
+    );
+
+    let _ = state.position_offers.insert(owner, PositionOffer {
+        to:         params.to,
+        expires_at: params.expires_at,
+    });
+
+    Ok(())
+}
+
+/// Accepts a position offered to the sender by `from`. The sender must not
+/// have a stake position of their own, not even one that only holds
+/// unbonding principal or unpaid reward.
+#[receive(
+    contract = "token-staking",
+    name = "acceptPosition",
+    parameter = "AccountAddress",
+    error = "ContractError",
+    mutable
+)]
+fn accept_position<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let from: AccountAddress = parse_parameter(ctx)?;
+    ensure_not_self(ctx)?;
+    let now = current_time(ctx);
+    let state = host.state_mut();
+
+    let offer = state
+        .position_offers
+        .remove_and_get(&from)
+        .ok_or(CustomContractError::NoPositionOffer)?;
+    ensure!(ctx.sender().matches_account(&offer.to), ContractError::Unauthorized);
+    ensure!(now < offer.expires_at, CustomContractError::OfferExpired.into());
+    ensure!(state.stake.get(&offer.to).is_none(), CustomContractError::RecipientHasPosition.into());
+
+    let position = state.stake.remove_and_get(&from).ok_or(CustomContractError::TokenNotFound)?;
+    ensure!(position.status == PositionStatus::Active, CustomContractError::PositionBusy.into());
+    state.ensure_within_cap(&offer.to, position.amount)?;
+    let _ = state.stake.insert(offer.to, position);
+    state.record_interaction(&offer.to, now);
+
+    Ok(())
+}
+
+#[derive(Serial, Deserial, SchemaType)]
+struct FundRewardPoolParams {
+    amount:                 u64,
+    token_contract_address: ContractAddress,
+}
+
+/// Transfers reward tokens from the sender into the contract's reward pool,
+/// which pays out all rewards.
+#[receive(
+    contract = "token-staking",
+    name = "fundRewardPool",
+    parameter = "FundRewardPoolParams",
+    error = "ContractError",
+    mutable
+)]
+fn fund_reward_pool<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let params: FundRewardPoolParams = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &params.token_contract_address)?;
+    ensure_supports_cis2(host, &params.token_contract_address)?;
+
+    host.state_mut().reward_pool += params.amount;
+    Cis2Client::transfer(
+        host,
+        TOKEN_ID,
+        params.token_contract_address,
+        concordium_cis2::TokenAmountU64(params.amount),
+        ctx.sender(),
+        self_receiver(ctx),
+        AdditionalData::empty(),
+    )
+    .map_err(CustomContractError::Cis2ClientError)?;
+
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+/// Accepts CIS-2 tokens sent to the contract. Custody inflows are accounted
+/// for by the entrypoint that initiated the transfer.
+#[receive(contract = "token-staking", name = "onReceivingCIS2", error = "ContractError")]
+fn on_receiving_cis2<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    _host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    ensure!(matches!(ctx.sender(), Address::Contract(_)), ContractError::Unauthorized);
+    Ok(())
+}
+
+#[derive(Serialize, SchemaType)]
+struct CustodyBalancesResponse {
+    held_principal: u64,
+    reward_pool:    u64,
+    /// The contract's balance as reported by the token contract.
+    balance:        u64,
+    /// Whether the tracked holdings match the reported balance.
+    in_sync:        bool,
+}
+
+/// Compares the principal and reward tracked in custody against the balance
+/// the token contract reports for this contract, to detect drift.
+#[receive(
+    contract = "token-staking",
+    name = "checkCustodyBalances",
+    parameter = "ContractAddress",
+    return_value = "CustodyBalancesResponse",
+    error = "ContractError"
+)]
+fn check_custody_balances<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<CustodyBalancesResponse> {
+    let token_contract_address: ContractAddress = parse_parameter(ctx)?;
+    ensure_token_contract(host.state(), &token_contract_address)?;
+    let TokenAmountU64(balance) = Cis2Client::balance_of(
+        host,
+        TOKEN_ID,
+        &token_contract_address,
+        Address::Contract(ctx.self_address()),
+    )
+    .map_err(CustomContractError::Cis2ClientError)?;
+
+    let state = host.state();
+    Ok(CustodyBalancesResponse {
+        held_principal: state.held_principal,
+        reward_pool:    state.reward_pool,
+        balance,
+        in_sync: state.held_principal + state.reward_pool == balance,
+    })
+}
+
+/// Returns the contract's own balance of the staked token, as reported by the
+/// token contract.
+#[receive(
+    contract = "token-staking",
+    name = "contractTokenBalance",
+    parameter = "ContractAddress",
+    return_value = "u64",
+    error = "ContractError"
+)]
+fn contract_token_balance<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    let token_contract_address: ContractAddress = parse_parameter(ctx)?;
+    ensure_token_contract(host.state(), &token_contract_address)?;
+    let TokenAmountU64(balance) = Cis2Client::balance_of(
+        host,
+        TOKEN_ID,
+        &token_contract_address,
+        Address::Contract(ctx.self_address()),
+    )
+    .map_err(CustomContractError::Cis2ClientError)?;
+    Ok(balance)
+}
+
+/// Sweeps dust to the treasury: the whole tokens of reward lost to rounding,
+/// which stayed in the reward pool, and tokens in the contract's balance
+/// beyond the principal and reward pool, e.g. sent to it directly. Rounding
+/// dust is swept only as far as the pool exceeds the reward owed, so that
+/// liabilities stay covered.
+#[receive(
+    contract = "token-staking",
+    name = "collectDust",
+    parameter = "ContractAddress",
+    error = "ContractError",
+    mutable
+)]
+fn collect_dust<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<()> {
+    let token_contract_address: ContractAddress = parse_parameter(ctx)?;
+    host.state_mut().enter_call()?;
+    ensure_token_contract(host.state(), &token_contract_address)?;
+    let now = current_time(ctx);
+    let state = host.state_mut();
+
+    ensure!(ctx.sender().matches_account(&state.admin), ContractError::Unauthorized);
+    ensure!(state.custody, CustomContractError::NotCustody.into());
+
+    let denominator = state.reward_denominator();
+    let owed = state.liabilities_at(now).min(u128::from(u64::MAX)) as u64;
+    let rounding = ((state.rounding_loss / denominator).min(u128::from(u64::MAX)) as u64)
+        .min(state.reward_pool.saturating_sub(owed));
+    state.reward_pool -= rounding;
+    state.rounding_loss -= u128::from(rounding) * denominator;
+
+    let liabilities = state.held_principal + state.reward_pool;
+    let treasury = state.treasury;
+    let TokenAmountU64(balance) = Cis2Client::balance_of(
+        host,
+        TOKEN_ID,
+        &token_contract_address,
+        Address::Contract(ctx.self_address()),
+    )
+    .map_err(CustomContractError::Cis2ClientError)?;
+
+    let dust = balance.saturating_sub(liabilities);
+    if dust > 0 {
+        Cis2Client::transfer(
+            host,
+            TOKEN_ID,
+            token_contract_address,
+            concordium_cis2::TokenAmountU64(dust),
+            Address::Contract(ctx.self_address()),
+            concordium_cis2::Receiver::Account(treasury),
+            AdditionalData::empty(),
+        )
+        .map_err(CustomContractError::Cis2ClientError)?;
+    }
+
+    host.state_mut().exit_call();
+    Ok(())
+}
+
+#[derive(Serialize, SchemaType)]
+struct RoundingLossResponse {
+    /// Cumulative remainder, in units of `1 / denominator` tokens.
+    remainder:    u128,
+    denominator:  u128,
+    /// The remainder in whole tokens, rounded down.
+    whole_tokens: u64,
+}
+
+/// Reports how much reward precision payouts have lost to rounding down.
+#[receive(
+    contract = "token-staking",
+    name = "viewRoundingLoss",
+    return_value = "RoundingLossResponse",
+    error = "ContractError"
+)]
+fn view_rounding_loss<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<RoundingLossResponse> {
+    let state = host.state();
+    let remainder = state.rounding_loss;
+    let denominator = state.reward_denominator();
+    Ok(RoundingLossResponse {
+        remainder,
+        denominator,
+        whole_tokens: (remainder / denominator) as u64,
+    })
+}
+
+/// Returns the reward pool as a ratio of all reward owed, in basis points, so
+/// monitoring can alert on under-collateralization. Reward accrued since
+/// positions were last settled is included as estimated for the pro-rata
+/// policy. Returns `u64::MAX` when no reward is owed, i.e. the pool is fully
+/// solvent.
+#[receive(
+    contract = "token-staking",
+    name = "solvencyRatio",
+    return_value = "u64",
+    error = "ContractError"
+)]
+fn solvency_ratio<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    let state = host.state();
+    let pending = state.liabilities_at(current_time(ctx));
+    if pending == 0 {
+        return Ok(u64::MAX);
+    }
+    let ratio = u128::from(state.reward_pool) * 10_000 / pending;
+    Ok(if ratio > u128::from(u64::MAX) { u64::MAX } else { ratio as u64 })
+}
+
+/// Estimates how many seconds the reward pool lasts at the current aggregate
+/// accrual rate of all stakers, so operators know when to refill it. Returns
+/// `u64::MAX` when nothing accrues, or the pool outlasts the season.
+///
+/// In pool-rate mode stake positions and lots drain `reward_per_second`; CCD
+/// positions and, otherwise, all stake accrue at the reward rate. With tiers
+/// every stake is assumed to earn the highest one, so the estimate errs on
+/// the short side. A season that has not started yet adds the time until it
+/// does.
+#[receive(
+    contract = "token-staking",
+    name = "viewRewardPoolRunway",
+    return_value = "u64",
+    error = "ContractError"
+)]
+fn view_reward_pool_runway<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    let state = host.state();
+    let now = current_time(ctx);
+    let halvings = match state.halving_interval {
+        Some(interval) => now.saturating_sub(state.program_start) / interval.saturating_mul(1000),
+        None => 0,
+    };
+    if !state.rewards_enabled || halvings >= MAX_HALVINGS {
+        return Ok(u64::MAX);
+    }
+    // Accrual starts at the later of the program and season start and stops
+    // at the season end.
+    let mut starts_at = now.max(state.program_start);
+    if let Some(season) = state.season {
+        if now >= season.end {
+            return Ok(u64::MAX);
+        }
+        starts_at = starts_at.max(season.start);
+    }
+    let rate_bps = state.apr_tiers.iter().map(|(_, bps)| *bps).max().unwrap_or(state.reward_rate_bps);
+    let (pool_rate, rate_staked) = match state.reward_per_second {
+        Some(rate) if state.total_staked > 0 => (rate, state.ccd_value(state.total_ccd_staked)),
+        Some(_) => (0, state.ccd_value(state.total_ccd_staked)),
+        None => (0, state.total_staked.saturating_add(state.ccd_value(state.total_ccd_staked))),
+    };
+    // The pool pays `pool_rate >> halvings` tokens per second, plus
+    // `rate_staked * rate_bps / 10_000 >> halvings` tokens per
+    // `reward_period` seconds.
+    let period = u128::from(state.reward_period) * 10_000;
+    let drain = u128::from(pool_rate) * period + u128::from(rate_staked) * u128::from(rate_bps);
+    if drain == 0 {
+        return Ok(u64::MAX);
+    }
+    let runway = ((u128::from(state.reward_pool) * period) << halvings) / drain;
+    let runway = runway + u128::from(starts_at - now) / 1000;
+    let depleted_at = u128::from(now) + runway * 1000;
+    if runway > u128::from(u64::MAX) || state.season.is_some_and(|season| depleted_at >= u128::from(season.end)) {
+        return Ok(u64::MAX);
+    }
+    Ok(runway as u64)
+}
+
+/// Returns the reward a claim by the account would pay out now, before any
+/// payout cap or pool shortfall applies.
+#[receive(
+    contract = "token-staking",
+    name = "viewPendingReward",
+    parameter = "AccountAddress",
+    return_value = "u64",
+    error = "ContractError"
+)]
+fn view_pending_reward<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    let owner: AccountAddress = parse_parameter(ctx)?;
+    let state = host.state();
+    let now = current_time(ctx);
+    let (reward, _) = state.get_reward(&owner, now)?;
+    let adjustment = state.stake.get(&owner).map_or(0, |owner_state| owner_state.adjustment);
+    let (claimable, _) = effective_claimable(reward, adjustment);
+    Ok(claimable)
+}
+
+/// Returns an account's stake position, or an empty one if it has none.
+#[receive(
+    contract = "token-staking",
+    name = "getStakeInfo",
+    parameter = "AccountAddress",
+    return_value = "StakeState",
+    error = "ContractError"
+)]
+fn get_stake_info<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<StakeState> {
+    let owner: AccountAddress = parse_parameter(ctx)?;
+    Ok(host.state().stake.get(&owner).map_or_else(StakeState::empty, |owner_state| owner_state.clone()))
+}
+
+/// Returns the principal staked across all token positions and lots.
+#[receive(
+    contract = "token-staking",
+    name = "viewTotalStaked",
+    return_value = "u64",
+    error = "ContractError"
+)]
+fn view_total_staked<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    Ok(host.state().total_staked)
+}
+
+/// Returns the principal an unstake by the account would return right now,
+/// after the unstake fee. This is the whole position, or zero while the
+/// position is locked or has an operation in flight. With a cooldown it is
+/// instead the unbonding principal once cooled down, or zero before. Only
+/// available in custody mode, where the contract holds the principal.
+#[receive(
+    contract = "token-staking",
+    name = "viewMaxWithdrawable",
+    parameter = "AccountAddress",
+    return_value = "u64",
+    error = "ContractError"
+)]
+fn view_max_withdrawable<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    let owner: AccountAddress = parse_parameter(ctx)?;
+    let state = host.state();
+    ensure!(state.custody, CustomContractError::NotCustody.into());
+    let now = current_time(ctx);
+    let owner_state = match state.stake.get(&owner) {
+        Some(owner_state) => owner_state,
+        None => return Ok(0),
+    };
+    let withdrawable = if state.cooldown_seconds > 0 {
+        let cooled_down_at = owner_state.unbonding_at.saturating_add(state.cooldown_seconds.saturating_mul(1000));
+        if now < cooled_down_at {
+            return Ok(0);
+        }
+        owner_state.unbonding_amount
+    } else {
+        if owner_state.status != PositionStatus::Active || now < owner_state.lock_until {
+            return Ok(0);
+        }
+        owner_state.amount
+    };
+    let fee = (u128::from(withdrawable) * u128::from(state.unstake_fee_bps) / 10_000) as u64;
+    Ok(withdrawable - fee)
+}
+
+/// Returns the earliest time in milliseconds at which the account could have
+/// all of its principal back, or zero if it could exit right now. This is
+/// the later of the lock expiry and the end of the cooldown. Principal still
+/// staked under a cooldown has to be requested first, which restarts the
+/// cooldown for all unbonding principal, so it counts from the later of the
+/// lock expiry and now.
+#[receive(
+    contract = "token-staking",
+    name = "effectiveUnlockTime",
+    parameter = "AccountAddress",
+    return_value = "u64",
+    error = "ContractError"
+)]
+fn effective_unlock_time<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    let owner: AccountAddress = parse_parameter(ctx)?;
+    let state = host.state();
+    let now = current_time(ctx);
+    let owner_state = match state.stake.get(&owner) {
+        Some(owner_state) => owner_state,
+        None => return Ok(0),
+    };
+    let cooldown_millis = state.cooldown_seconds.saturating_mul(1000);
+    let mut unlock_at = owner_state.lock_until;
+    if owner_state.unbonding_amount > 0 {
+        unlock_at = unlock_at.max(owner_state.unbonding_at.saturating_add(cooldown_millis));
+    }
+    if owner_state.amount > 0 && cooldown_millis > 0 {
+        unlock_at = unlock_at.max(owner_state.lock_until.max(now).saturating_add(cooldown_millis));
+    }
+    Ok(if unlock_at > now { unlock_at } else { 0 })
+}
+
+/// Returns the number of accounts with principal staked in a stake position
+/// or in lots.
+#[receive(
+    contract = "token-staking",
+    name = "getStakersCount",
+    return_value = "u64",
+    error = "ContractError"
+)]
+fn get_stakers_count<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<u64> {
+    Ok(host.state().staker_count)
+}
+
+#[derive(Serialize, SchemaType)]
+struct ListStakersParams {
+    /// Number of positions to skip.
+    start: u64,
+    /// Number of positions to return, at most `MAX_LIST_STAKERS`.
+    limit: u64,
+}
+
+/// Returns up to `limit` stake positions after skipping `start`, for
+/// enumerating stakers page by page. Rejects limits above
+/// `MAX_LIST_STAKERS` with `LimitTooLarge`.
+#[receive(
+    contract = "token-staking",
+    name = "listStakers",
+    parameter = "ListStakersParams",
+    return_value = "Vec<(AccountAddress, StakeState)>",
+    error = "ContractError"
+)]
+fn list_stakers<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<Vec<(AccountAddress, StakeState)>> {
+    let params: ListStakersParams = parse_parameter(ctx)?;
+    ensure!(params.limit <= MAX_LIST_STAKERS, CustomContractError::LimitTooLarge.into());
+    Ok(host
+        .state()
+        .stake
+        .iter()
+        .skip(params.start as usize)
+        .take(params.limit as usize)
+        .map(|(owner, owner_state)| (*owner, owner_state.clone()))
+        .collect())
+}
+
+#[derive(Serialize, SchemaType, Default, Clone, Copy)]
+struct StakeAgeBucket {
+    count:  u64,
+    amount: u64,
+}
+
+#[derive(Serialize, SchemaType)]
+struct StakeAgeBucketsResponse {
+    /// Positions staked below one day, one week, one month, and longer.
+    buckets: [StakeAgeBucket; 4],
+    /// Number of positions to skip to continue the scan, if it stopped at
+    /// `MAX_BUCKET_SCAN` positions.
+    next:    Option<u64>,
+}
+
+/// Buckets stake positions by the time since their last deposit, for a
+/// maturity distribution. Positions with nothing staked, e.g. only awaiting
+/// a withdrawal, are left out. Takes the number of positions to skip, scans
+/// at most `MAX_BUCKET_SCAN` of them and returns where to continue.
+#[receive(
+    contract = "token-staking",
+    name = "viewStakeAgeBuckets",
+    parameter = "u64",
+    return_value = "StakeAgeBucketsResponse",
+    error = "ContractError"
+)]
+fn view_stake_age_buckets<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<StakeAgeBucketsResponse> {
+    let skip: u64 = parse_parameter(ctx)?;
+    let now = current_time(ctx);
+    let mut buckets = [StakeAgeBucket::default(); 4];
+    let mut next = None;
+    for (scanned, (_, owner_state)) in host.state().stake.iter().skip(skip as usize).enumerate() {
+        if scanned == MAX_BUCKET_SCAN {
+            next = Some(skip + scanned as u64);
+            break;
+        }
+        if owner_state.amount == 0 {
+            continue;
+        }
+        let age = now.saturating_sub(owner_state.deposited_at);
+        let index = STAKE_AGE_BUCKET_BOUNDS.iter().take_while(|bound| age >= **bound).count();
+        buckets[index].count += 1;
+        buckets[index].amount = buckets[index].amount.saturating_add(owner_state.amount);
+    }
+    Ok(StakeAgeBucketsResponse {
+        buckets,
+        next,
+    })
+}
+
+#[derive(Serialize, SchemaType)]
+struct ViewTimingResponse {
+    staked_start_at_millis: u64,
+    now_millis:             u64,
+    elapsed_seconds:        u64,
+}
+
+/// Exposes the raw inputs and result of the staking time computation for an
+/// account, without inserting an entry for unknown accounts.
+#[receive(
+    contract = "token-staking",
+    name = "viewTiming",
+    parameter = "AccountAddress",
+    return_value = "ViewTimingResponse",
+    error = "ContractError"
+)]
+fn view_timing<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<ViewTimingResponse> {
+    let owner: AccountAddress = parse_parameter(ctx)?;
+    let state = host.state();
+    let now_millis = current_time(ctx);
+    let staked_start_at_millis = state.stake.get(&owner).map_or(0, |owner_state| owner_state.staked_start_at);
+
+    Ok(ViewTimingResponse {
+        staked_start_at_millis,
+        now_millis,
+        elapsed_seconds: state.get_time(&owner, now_millis)?,
+    })
+}
+
+#[derive(Serialize, SchemaType)]
+struct ProjectedMaturityValueResponse {
+    /// Time in milliseconds the projection is made for.
+    matures_at: u64,
+    /// Principal plus the reward accrued by `matures_at` at the current rate.
+    value:      u64,
+}
+
+/// Projects the value of an account's position at maturity, so clients can
+/// show what it will be worth. Positions have no fixed term, so they are
+/// projected `DEFAULT_PROJECTION_HORIZON_MILLIS` ahead of now.
+#[receive(
+    contract = "token-staking",
+    name = "projectedMaturityValue",
+    parameter = "AccountAddress",
+    return_value = "ProjectedMaturityValueResponse",
+    error = "ContractError"
+)]
+fn projected_maturity_value<S: HasStateApi>(
+    ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<ProjectedMaturityValueResponse> {
+    let owner: AccountAddress = parse_parameter(ctx)?;
+    let state = host.state();
+    let now = current_time(ctx);
+    let matures_at = now.saturating_add(DEFAULT_PROJECTION_HORIZON_MILLIS);
+    let principal = state.stake.get(&owner).map_or(0, |owner_state| owner_state.amount);
+    let (reward, _) = state.get_reward(&owner, matures_at)?;
+
+    Ok(ProjectedMaturityValueResponse {
+        matures_at,
+        value: principal.saturating_add(reward),
+    })
+}
+
+#[derive(Serialize, SchemaType)]
+struct FormatAmountsResponse {
+    decimals: u8,
+}
+
+/// Returns the display configuration for token amounts, so clients can render
+/// amounts without hardcoding the token decimals.
+#[receive(
+    contract = "token-staking",
+    name = "formatAmounts",
+    return_value = "FormatAmountsResponse",
+    error = "ContractError"
+)]
+fn format_amounts<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<FormatAmountsResponse> {
+    Ok(FormatAmountsResponse {
+        decimals: host.state().token_decimals,
+    })
+}
+
+/// A behavior of the staking contract that tooling can discover.
+#[derive(Serialize, SchemaType, PartialEq, Eq)]
+enum Capability {
+    /// Principal stays in the staker's wallet and the contract acts as a
+    /// CIS-2 operator for it.
+    OperatorStaking,
+    /// Principal and reward are held by the contract.
+    Custody,
+    /// Native CCD can be staked with `stakeCcd`.
+    CcdStaking,
+    /// Independently locked lots can be staked with `stakeNewLot`.
+    Lots,
+    /// Claimed reward vests, released with `claimVested`.
+    Vesting,
+    /// Unstake reward is paid in installments, claimed with
+    /// `claimInstallment`.
+    Installments,
+    /// Referrers earn a bonus, claimed with `claimReferralReward`.
+    Referrals,
+    /// Accrual is limited to a staking season.
+    Seasons,
+}
+
+#[derive(Serialize, SchemaType)]
+struct CapabilitiesResponse {
+    /// Format version of logged events, see `EVENT_FORMAT_VERSION`.
+    event_format_version: u8,
+    capabilities:         Vec<Capability>,
+}
+
+/// Reports the behaviors this instance implements, so tooling can discover
+/// them generically, in the spirit of CIS-0 `supports`.
+#[receive(
+    contract = "token-staking",
+    name = "capabilities",
+    return_value = "CapabilitiesResponse",
+    error = "ContractError"
+)]
+fn capabilities<S: HasStateApi>(
+    _ctx: &impl HasReceiveContext,
+    host: &impl HasHost<State<S>, StateApiType = S>,
+) -> ContractResult<CapabilitiesResponse> {
+    let state = host.state();
+    let mut capabilities = vec![
+        if state.custody {
+            Capability::Custody
+        } else {
+            Capability::OperatorStaking
+        },
+        Capability::Lots,
+    ];
+    if state.tokens_per_ccd.is_some() {
+        capabilities.push(Capability::CcdStaking);
+    }
+    if state.vesting.is_some() {
+        capabilities.push(Capability::Vesting);
+    }
+    if state.installments.is_some() {
+        capabilities.push(Capability::Installments);
+    }
+    if state.referral_bonus_bps > 0 {
+        capabilities.push(Capability::Referrals);
+    }
+    if state.season.is_some() {
+        capabilities.push(Capability::Seasons);
+    }
+    Ok(CapabilitiesResponse {
+        event_format_version: EVENT_FORMAT_VERSION,
+        capabilities,
+    })
+}
+
+/// Current time in milliseconds, as seen by reward computation. This is the
+/// block's slot time, unless overridden with `set_test_clock`.
+fn current_time(ctx: &impl HasCommonData) -> u64 {
+    #[cfg(any(test, feature = "test-clock"))]
+    if let Some(now) = TEST_CLOCK.with(|clock| clock.get()) {
+        return now;
+    }
+    ctx.metadata().slot_time().timestamp_millis()
+}
+
+#[cfg(any(test, feature = "test-clock"))]
+std::thread_local! {
+    static TEST_CLOCK: core::cell::Cell<Option<u64>> = const { core::cell::Cell::new(None) };
+}
+
+/// Overrides the time in milliseconds seen by the contract on the current
+/// thread, or restores the slot time with `None`. Only available with the
+/// `test-clock` feature and in the crate's own tests, for deterministic tests
+/// of the reward math.
+#[cfg(any(test, feature = "test-clock"))]
+pub fn set_test_clock(now: Option<u64>) { TEST_CLOCK.with(|clock| clock.set(now)); }
+
+fn calculate_reward<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    ctx: &impl HasReceiveContext<()>,
+    owner: &AccountAddress,
+) -> ContractResult<u64> {
+    host.state_mut().take_reward(owner, current_time(ctx))
+}
+
+/// Transfers `reward` of the staked token to the invoker, out of the reward
+/// pool held by the contract.
+fn transfer_reward<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    ctx: &impl HasReceiveContext,
+    token_contract_address: ContractAddress,
+    reward: u64,
+) -> ContractResult<()> {
+    // With rewards disabled nothing is owed, and no reward pool or operator
+    // approval exists to pay from. Nothing owed is not transferred either.
+    if !host.state().rewards_enabled || reward == 0 {
+        return Ok(());
+    }
+    let from = reward_source(host, ctx, reward)?;
+    // Check the pool's actual balance, not just the ledger, so that a payout
+    // the token contract cannot cover is rejected with `NoBalance` rather
+    // than failing inside the transfer. Either way the whole transaction,
+    // including the already updated position, is rolled back.
+    let funded = Cis2Client::has_balance(host, TOKEN_ID, &token_contract_address, reward, from)
+        .map_err(CustomContractError::Cis2ClientError)?;
+    ensure!(funded, CustomContractError::NoBalance.into());
+    let data = payout_data(host.state(), PayoutKind::Reward, &ctx.invoker());
+    Cis2Client::transfer(
+        host,
+        TOKEN_ID,
+        token_contract_address,
+        concordium_cis2::TokenAmountU64(reward),
+        from,
+        concordium_cis2::Receiver::Account(ctx.invoker()),
+        data,
+    )
+    .map_err(CustomContractError::Cis2ClientError)?;
+    Ok(())
+}
+
+/// Pays out `reward` claimed by the owner at `now`: under the vesting
+/// schedule if one is configured, otherwise by transferring it right away.
+fn pay_reward<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    ctx: &impl HasReceiveContext,
+    token_contract_address: ContractAddress,
+    owner: &AccountAddress,
+    reward: u64,
+    now: u64,
+) -> ContractResult<()> {
+    if reward == 0 {
+        return Ok(());
+    }
+    if host.state().vesting.is_some() {
+        host.state_mut().record_vesting(owner, reward, now);
+        return Ok(());
+    }
+    transfer_reward(host, ctx, token_contract_address, reward)
+}
+
+/// Staking action a payout transfer is made for.
+#[derive(Clone, Copy)]
+enum PayoutKind {
+    Reward,
+    Principal,
+    Fee,
+}
+
+/// Additional data attached to a payout transfer. With `payout_memo` it is
+/// a tag byte for the kind of payout followed by the 32 bytes of the account
+/// whose staking action made it, so receive hooks and indexers can correlate
+/// the payment. Otherwise it is empty.
+fn payout_data<S: HasStateApi>(state: &State<S>, kind: PayoutKind, account: &AccountAddress) -> AdditionalData {
+    if !state.payout_memo {
+        return AdditionalData::empty();
+    }
+    let mut memo = Vec::with_capacity(33);
+    memo.push(kind as u8);
+    memo.extend_from_slice(&account.0);
+    AdditionalData::from(memo)
+}
+
+/// Debits `reward` from the reward pool and the liabilities, and returns the
+/// contract's own address, which holds the pool, to pay it from.
+fn reward_source<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    ctx: &impl HasReceiveContext,
+    reward: u64,
+) -> ContractResult<Address> {
+    let state = host.state_mut();
+    state.reward_pool =
+        state.reward_pool.checked_sub(reward).ok_or(CustomContractError::InsufficientRewardPool)?;
+    state.forfeit_reward(reward);
+    Ok(Address::Contract(ctx.self_address()))
+}
+
+/// Withholds the unstake fee from `principal` held in custody and routes it to
+/// the configured destination. Returns the fee.
+fn charge_unstake_fee<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    ctx: &impl HasReceiveContext,
+    token_contract_address: ContractAddress,
+    owner: &AccountAddress,
+    principal: u64,
+    logger: &mut impl HasLogger,
+) -> ContractResult<u64> {
+    let state = host.state_mut();
+    let fee = (u128::from(principal) * u128::from(state.unstake_fee_bps) / 10_000) as u64;
+    if fee == 0 {
+        return Ok(0);
+    }
+    state.held_principal -= fee;
+    let destination = state.fee_destination;
+    match destination {
+        FeeDestination::RewardPool => state.reward_pool += fee,
+        FeeDestination::Treasury => {
+            let treasury = state.treasury;
+            let data = payout_data(state, PayoutKind::Fee, owner);
+            Cis2Client::transfer(
+                host,
+                TOKEN_ID,
+                token_contract_address,
+                concordium_cis2::TokenAmountU64(fee),
+                Address::Contract(ctx.self_address()),
+                concordium_cis2::Receiver::Account(treasury),
+                data,
+            )
+            .map_err(CustomContractError::Cis2ClientError)?;
+        }
+    }
+    log_event(host.state(), logger, &StakingEvent::UnstakeFeeCharged {
+        owner:  *owner,
+        amount: fee,
+        destination,
+    })?;
+    Ok(fee)
+}
+
+/// Returns principal held in custody to `owner`.
+fn transfer_principal<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    ctx: &impl HasReceiveContext,
+    token_contract_address: ContractAddress,
+    owner: &AccountAddress,
+    amount: u64,
+) -> ContractResult<()> {
+    host.state_mut().held_principal -= amount;
+    let data = payout_data(host.state(), PayoutKind::Principal, &ctx.invoker());
+    Cis2Client::transfer(
+        host,
+        TOKEN_ID,
+        token_contract_address,
+        concordium_cis2::TokenAmountU64(amount),
+        Address::Contract(ctx.self_address()),
+        concordium_cis2::Receiver::Account(*owner),
+        data,
+    )
+    .map_err(CustomContractError::Cis2ClientError)?;
+    Ok(())
+}
+
+/// Rejects token contract addresses other than the one the contract was
+/// initialized with, so that a caller cannot stake against one token and be
+/// paid out in another.
+fn ensure_token_contract<S: HasStateApi>(
+    state: &State<S>,
+    token_contract_address: &ContractAddress,
+) -> ContractResult<()> {
+    ensure!(*token_contract_address == state.token_contract, CustomContractError::WrongTokenContract.into());
+    Ok(())
+}
+
+/// Rejects token contracts that do not advertise CIS-2 support, before any
+/// tokens are moved into the contract. A contract that does not answer
+/// `supports` does not implement CIS-2 either.
+fn ensure_supports_cis2<S: HasStateApi>(
+    host: &impl HasHost<State<S>, StateApiType = S>,
+    token_contract_address: &ContractAddress,
+) -> ContractResult<()> {
+    let supported = Cis2Client::supports_cis2(host, token_contract_address).unwrap_or(false);
+    ensure!(supported, CustomContractError::UnsupportedTokenContract.into());
+    Ok(())
+}
+
+/// The receiver for CIS-2 transfers into this contract.
+fn self_receiver(ctx: &impl HasReceiveContext) -> Receiver {
+    Receiver::Contract(
+        ctx.self_address(),
+        OwnedEntrypointName::new_unchecked(ON_RECEIVING_CIS2_ENTRYPOINT_NAME.into()),
+    )
+}
+
+/// Caps a payout at the configured `max_reward_per_tx`, logging a
+/// `RewardClamped` event whenever the cap applies. The reward above the cap
+/// is forfeited, whichever entrypoint pays it out.
+fn clamp_reward<S: HasStateApi>(
+    state: &mut State<S>,
+    owner: &AccountAddress,
+    reward: u64,
+    logger: &mut impl HasLogger,
+) -> ContractResult<u64> {
+    match state.max_reward_per_tx {
+        Some(max) if reward > max => {
+            log_event(state, logger, &StakingEvent::RewardClamped {
+                owner:    *owner,
+                computed: reward,
+                paid:     max,
+            })?;
+            state.forfeit_reward(reward - max);
+            Ok(max)
+        }
+        _ => Ok(reward),
+    }
+}
+
+/// Keeps `amount` of reward left unpaid by an exit owed to the owner, logging
+/// a `RewardShortfall` event for it.
+fn record_shortfall<S: HasStateApi>(
+    state: &mut State<S>,
+    logger: &mut impl HasLogger,
+    owner: &AccountAddress,
+    amount: u64,
+    now: u64,
+) -> ContractResult<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    state.record_residual(owner, amount, now);
+    log_event(state, logger, &StakingEvent::RewardShortfall {
+        owner: *owner,
+        amount,
+    })
+}
+
+/// Logs `event` unless the configured event level filters it out.
+fn log_event<S: HasStateApi>(
+    state: &State<S>,
+    logger: &mut impl HasLogger,
+    event: &StakingEvent,
+) -> ContractResult<()> {
+    let enabled = match state.event_level {
+        EventLevel::Off => false,
+        EventLevel::Financial => event.is_financial(),
+        EventLevel::All => true,
+    };
+    if enabled {
+        logger.log(&VersionedEvent(event))?;
+    }
+    Ok(())
+}
+
+/// Reader yielding `first` before the rest of the source.
+struct Prefixed<'a, R> {
+    first: Option<u8>,
+    rest:  &'a mut R,
+}
+
+impl<R: Read> Read for Prefixed<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> ParseResult<usize> {
+        if let (Some(first), Some(head)) = (self.first, buf.first_mut()) {
+            *head = first;
+            self.first = None;
+            return Ok(1);
+        }
+        self.rest.read(buf)
+    }
+}
+
+pub struct Cis2Client;
+
+impl Cis2Client {
+    pub(crate) fn is_operator_of<S: HasStateApi>(
+        host: &impl HasHost<State<S>, StateApiType = S>,
+        owner: Address,
+        current_contract_address: ContractAddress,
+        token_contract_address: &ContractAddress,
+    ) -> Result<bool, Cis2ClientError> {
+        let params = &OperatorOfQueryParams {
+            queries: vec![OperatorOfQuery {
+                owner,
+                address: Address::Contract(current_contract_address),
+            }],
+        };
+
+        let parsed_res: OperatorOfQueryResponse = Cis2Client::invoke_contract_read_only(
+            host,
+            token_contract_address,
+            OPERATOR_OF_ENTRYPOINT_NAME,
+            params,
+        )?;
+
+        let is_operator = single_response(parsed_res.0)?;
+
+        Ok(is_operator)
+    }
+
+    /// Whether the token contract advertises CIS-2 support through CIS-0
+    /// `supports`. Support by another contract address does not count, as
+    /// all calls go to the token contract itself.
+    pub(crate) fn supports_cis2<S: HasStateApi>(
+        host: &impl HasHost<State<S>, StateApiType = S>,
+        token_contract_address: &ContractAddress,
+    ) -> Result<bool, Cis2ClientError> {
+        let params = SupportsQueryParams {
+            queries: vec![CIS2_STANDARD_IDENTIFIER.to_owned()],
+        };
+        let parsed_res: SupportsQueryResponse = Cis2Client::invoke_contract_read_only(
+            host,
+            token_contract_address,
+            SUPPORTS_ENTRYPOINT_NAME,
+            &params,
+        )?;
+
+        Ok(matches!(single_response(parsed_res.results)?, SupportResult::Support))
+    }
+
+    pub(crate) fn has_balance<S: HasStateApi>(
+        host: &impl HasHost<State<S>, StateApiType = S>,
+        token_id: ContractTokenId,
+        token_contract_address: &ContractAddress,
+        amount: u64,
+        owner: Address,
+    ) -> Result<bool, Cis2ClientError> {
+        let balance = Cis2Client::balance_of(host, token_id, token_contract_address, owner)?;
+
+        Result::Ok(balance.cmp(&TokenAmountU64(amount)).is_ge())
+    }
+
+    pub(crate) fn balance_of<S: HasStateApi>(
+        host: &impl HasHost<State<S>, StateApiType = S>,
+        token_id: ContractTokenId,
+        token_contract_address: &ContractAddress,
+        owner: Address,
+    ) -> Result<ContractTokenAmount, Cis2ClientError> {
+        let params = ContractBalanceOfQueryParams {
+            queries: vec![BalanceOfQuery {
+                token_id,
+                address: owner,
+            }],
+        };
+
+        let parsed_res: ContractBalanceOfQueryResponse = Cis2Client::invoke_contract_read_only(
+            host,
+            token_contract_address,
+            BALANCE_OF_ENTRYPOINT_NAME,
+            &params,
+        )?;
+
+        let balance = single_response(parsed_res.0)?;
+
+        Ok(balance)
+    }
+
+    pub(crate) fn transfer<S: HasStateApi>(
+        host: &mut impl HasHost<State<S>, StateApiType = S>,
+        token_id: TokenIdUnit,
+        token_contract_address: ContractAddress,
+        amount: ContractTokenAmount,
+        from: Address,
+        to: Receiver,
+        data: AdditionalData,
+    ) -> Result<bool, Cis2ClientError> {
+        let params: TransferParameter = TransferParams(vec![Transfer {
+            token_id,
+            amount,
+            from,
+            data,
+            to,
+        }]);
+
+        Cis2Client::invoke_contract(host, &token_contract_address, TRANSFER_ENTRYPOINT_NAME, &params)?;
+
+        Result::Ok(true)
+    }
+    
+    fn invoke_contract_read_only<S: HasStateApi, R: Deserial, P: Serial>(
+        host: &impl HasHost<State<S>, StateApiType = S>,
+        contract_address: &ContractAddress,
+        entrypoint_name: &str,
+        params: &P,
+    ) -> Result<R, Cis2ClientError> {
+        let invoke_contract_result = host
+            .invoke_contract_read_only(
+                contract_address,
+                params,
+                EntrypointName::new(entrypoint_name).unwrap_abort(),
+                Amount::from_ccd(0),
+            )
+            .map_err(|_e| Cis2ClientError::InvokeContractError)?;
+        let mut invoke_contract_res = match invoke_contract_result {
+            Some(s) => s,
+            None => return Result::Err(Cis2ClientError::InvokeContractError),
+        };
+        let parsed_res =
+            R::deserial(&mut invoke_contract_res).map_err(|_e| Cis2ClientError::ParseResult)?;
+
+        Ok(parsed_res)
+    }
+
+    /// Invokes an entrypoint that may change the invoked contract's state,
+    /// such as a CIS-2 `transfer`. This contract's state is committed before
+    /// the call. Returns whether this contract's state was modified during the
+    /// call, and the return value, if any.
+    fn invoke_contract<S: HasStateApi, H: HasHost<State<S>, StateApiType = S>, P: Serial>(
+        host: &mut H,
+        contract_address: &ContractAddress,
+        entrypoint_name: &str,
+        params: &P,
+    ) -> Result<(bool, Option<H::ReturnValueType>), Cis2ClientError> {
+        host.invoke_contract(
+            contract_address,
+            params,
+            EntrypointName::new(entrypoint_name).unwrap_abort(),
+            Amount::from_ccd(0),
+        )
+        .map_err(|_e| Cis2ClientError::InvokeContractError)
+    }
+}
+
+/// Extracts the answer to a single-query CIS-2 request. CIS-2 responses are
+/// positional, so anything other than exactly one entry cannot be matched to
+/// the query and is rejected.
+fn single_response<T>(mut responses: Vec<T>) -> Result<T, Cis2ClientError> {
+    ensure!(responses.len() == 1, Cis2ClientError::InvalidResponse);
+    responses.pop().ok_or(Cis2ClientError::InvalidResponse)
+}
+
+/// Parses the whole parameter as `T`. Bytes left over after it are rejected
+/// with `ParseParams`, like truncated input, rather than silently ignored.
+fn parse_parameter<T: Deserial>(ctx: &impl HasCommonData) -> ContractResult<T> {
+    let mut cursor = ctx.parameter_cursor();
+    let params = T::deserial(&mut cursor)?;
+    ensure_consumed(&mut cursor)?;
+    Ok(params)
+}
+
+/// Parses a batch parameter, rejecting more than `MAX_BATCH_STAKE` entries
+/// with `LimitTooLarge` from the length prefix alone, before parsing any
+/// entry.
+fn parse_batch<T: Deserial>(ctx: &impl HasCommonData) -> ContractResult<Vec<T>> {
+    let mut cursor = ctx.parameter_cursor();
+    let len = u32::deserial(&mut cursor)? as usize;
+    ensure!(len <= MAX_BATCH_STAKE, CustomContractError::LimitTooLarge.into());
+    let mut entries = Vec::with_capacity(len);
+    for _ in 0..len {
+        entries.push(T::deserial(&mut cursor)?);
+    }
+    ensure_consumed(&mut cursor)?;
+    Ok(entries)
+}
+
+/// Rejects a parameter with bytes left after the parsed value.
+fn ensure_consumed(cursor: &mut impl HasParameter) -> ContractResult<()> {
+    let position = cursor.seek(SeekFrom::Current(0)).map_err(|_| CustomContractError::ParseParams)?;
+    ensure!(position == cursor.size(), CustomContractError::ParseParams.into());
+    Ok(())
+}
+
+/// Rejects calls the contract makes to itself, e.g. from a callback. Staker
+/// entrypoints act on behalf of accounts, which the contract never is.
+fn ensure_not_self(ctx: &impl HasReceiveContext) -> Result<(), CustomContractError> {
+    ensure!(
+        ctx.sender() != Address::Contract(ctx.self_address()),
+        CustomContractError::SelfInvocation
+    );
+    Ok(())
+}
+
+fn ensure_is_operator<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    ctx: &impl HasReceiveContext<()>,
+    token_contract_address: &ContractAddress,
+) -> Result<(), CustomContractError> {
+    let is_operator = Cis2Client::is_operator_of(
+        host,
+        ctx.sender(),
+        ctx.self_address(),
+        token_contract_address,
+    )
+    .map_err(CustomContractError::Cis2ClientError)?;
+    ensure!(is_operator, CustomContractError::NotOperator);
+    Ok(())
+}
+
+fn ensure_balance<S: HasStateApi>(
+    host: &mut impl HasHost<State<S>, StateApiType = S>,
+    token_id: ContractTokenId,
+    token_contract_address: &ContractAddress,
+    amount: u64,
+    ctx: &impl HasReceiveContext<()>,
+) -> Result<(), CustomContractError> {
+    let has_balance = Cis2Client::has_balance(host, token_id, token_contract_address, amount, ctx.sender())
+        .map_err(CustomContractError::Cis2ClientError)?;
+    ensure!(has_balance, CustomContractError::NoBalance);
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(deprecated)]
+mod tests {
+    use super::*;
+    use concordium_std::test_infrastructure::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    const ADMIN: AccountAddress = AccountAddress([0u8; 32]);
+    const ALICE: AccountAddress = AccountAddress([1u8; 32]);
+    const BOB: AccountAddress = AccountAddress([2u8; 32]);
+    const TREASURY: AccountAddress = AccountAddress([3u8; 32]);
+    const SELF_ADDRESS: ContractAddress = ContractAddress {
+        index:    1,
+        subindex: 0,
+    };
+    const TOKEN: ContractAddress = ContractAddress {
+        index:    2,
+        subindex: 0,
+    };
+    /// Time in milliseconds at which the program starts.
+    const START: u64 = 1_700_000_000_000;
+    /// One year of `SECOND_PER_YEAR` seconds, in milliseconds.
+    const YEAR_MILLIS: u64 = 31_536_000_000;
+
+    type Host = TestHost<State<TestStateApi>>;
+    /// `(from, to, amount)` of every transfer made on the token contract.
+    type Transfers = Rc<RefCell<Vec<(Address, Receiver, u64)>>>;
+
+    /// Terms paying the whole staked amount once per year, with no limits.
+    fn init_params() -> InitParams {
+        InitParams {
+            no_accrual_windows:       Vec::new(),
+            max_reward_per_tx:        None,
+            residual_claim_window:    0,
+            token_decimals:           6,
+            event_level:              EventLevel::All,
+            custody:                  true,
+            halving_interval_seconds: None,
+            treasury:                 TREASURY,
+            reward_period_seconds:    *SECOND_PER_YEAR,
+            auto_claim_on_top_up:     false,
+            max_claims_per_block:     None,
+            vesting:                  None,
+            installments:             None,
+            unstake_fee_bps:          0,
+            fee_destination:          FeeDestination::Treasury,
+            shortfall_policy:         ShortfallPolicy::FirstComeFirstServed,
+            season:                   None,
+            referral_bonus_bps:       0,
+            program_start:            None,
+            reclaim_after_seconds:    None,
+            reward_per_second:        None,
+            reward_rate_per_year_bps: 10_000,
+            rewards_enabled:          true,
+            min_lock_seconds:         0,
+            early_exit_decay:         None,
+            payout_memo:              false,
+            token_contract:           TOKEN,
+            cooldown_seconds:         0,
+            max_stake_per_account:    None,
+            min_stake_amount:         0,
+            apr_tiers:                Vec::new(),
+            tokens_per_ccd:           None,
+        }
+    }
+
+    /// A host for a contract started at `START` under `params`, with a token
+    /// contract that supports CIS-2, reports ample balances and operators,
+    /// and records its transfers.
+    fn setup(params: InitParams) -> (Host, Transfers) {
+        let mut state_builder = TestStateBuilder::new();
+        let state = State::empty(&mut state_builder, params, ADMIN, START);
+        let mut host = TestHost::new(state, state_builder);
+        host.set_self_address(SELF_ADDRESS);
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(SUPPORTS_ENTRYPOINT_NAME.into()),
+            MockFn::new_v1(|_, _, _, _| {
+                Ok((false, SupportsQueryResponse {
+                    results: vec![SupportResult::Support],
+                }))
+            }),
+        );
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(BALANCE_OF_ENTRYPOINT_NAME.into()),
+            MockFn::new_v1(|_, _, _, _| {
+                Ok((false, ContractBalanceOfQueryResponse::from(vec![TokenAmountU64(u64::MAX)])))
+            }),
+        );
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(OPERATOR_OF_ENTRYPOINT_NAME.into()),
+            MockFn::new_v1(|_, _, _, _| Ok((false, OperatorOfQueryResponse::from(vec![true])))),
+        );
+        let transfers = Transfers::default();
+        let recorded = transfers.clone();
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(TRANSFER_ENTRYPOINT_NAME.into()),
+            MockFn::new_v0::<(), _>(move |parameter, _, _, _| {
+                let params: TransferParameter =
+                    from_bytes(parameter.as_ref()).map_err(|_| CallContractError::Trap)?;
+                for transfer in params.0 {
+                    recorded.borrow_mut().push((transfer.from, transfer.to, transfer.amount.0));
+                }
+                Ok(false)
+            }),
+        );
+        (host, transfers)
+    }
+
+    /// Makes the token contract report `balance` for every account.
+    fn mock_balance(host: &mut Host, balance: u64) {
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(BALANCE_OF_ENTRYPOINT_NAME.into()),
+            MockFn::new_v1(move |_, _, _, _| {
+                Ok((false, ContractBalanceOfQueryResponse::from(vec![TokenAmountU64(balance)])))
+            }),
+        );
+    }
+
+    /// A call by `sender` at `now` milliseconds with `parameter`.
+    fn receive_ctx(sender: AccountAddress, now: u64, parameter: &[u8]) -> TestReceiveContext<'_> {
+        let mut ctx = TestReceiveContext::empty();
+        ctx.set_sender(Address::Account(sender))
+            .set_invoker(sender)
+            .set_owner(ADMIN)
+            .set_self_address(SELF_ADDRESS)
+            .set_metadata_slot_time(Timestamp::from_timestamp_millis(now))
+            .set_parameter(parameter);
+        ctx
+    }
+
+    /// Tokens the contract transferred out of its own holdings to `account`.
+    fn paid_to(transfers: &Transfers, account: AccountAddress) -> Vec<u64> {
+        transfers
+            .borrow()
+            .iter()
+            .filter(|(from, to, _)| {
+                *from == Address::Contract(SELF_ADDRESS) && matches!(to, Receiver::Account(to) if *to == account)
+            })
+            .map(|(_, _, amount)| *amount)
+            .collect()
+    }
+
+    /// Runs a call as a transaction: rejected calls are rolled back and the
+    /// state of successful ones is committed, as on chain. The test host only
+    /// commits on calls out, so without this a later rollback would also undo
+    /// effects applied after the last of those.
+    fn transact<R>(host: &mut Host, call: impl FnOnce(&mut Host) -> ContractResult<R>) -> ContractResult<R> {
+        let result = host.with_rollback(call)?;
+        host.commit_state();
+        Ok(result)
+    }
+
+    /// The bytes logged for `event`.
+    fn logged(event: &StakingEvent) -> Vec<u8> { to_bytes(&VersionedEvent(event)) }
+
+    fn stake(host: &mut Host, owner: AccountAddress, amount: u64, now: u64) -> ContractResult<TestLogger> {
+        let parameter = to_bytes(&StakeParams {
+            owner,
+            amount,
+            token_contract_address: TOKEN,
+            idempotency_key: None,
+            referrer: None,
+        });
+        let mut logger = TestLogger::init();
+        transact(host, |host| {
+            stake_token(&receive_ctx(owner, now, &parameter), host, &mut logger)
+        })?;
+        Ok(logger)
+    }
+
+    fn unstake(host: &mut Host, owner: AccountAddress, amount: u64, now: u64) -> ContractResult<TestLogger> {
+        let parameter = to_bytes(&UnStakeParams {
+            owner,
+            token_contract_address: TOKEN,
+            amount,
+        });
+        let mut logger = TestLogger::init();
+        transact(host, |host| {
+            unstake_token(&receive_ctx(owner, now, &parameter), host, &mut logger)
+        })?;
+        Ok(logger)
+    }
+
+    fn claim(host: &mut Host, owner: AccountAddress, now: u64) -> ContractResult<TestLogger> {
+        let parameter = to_bytes(&ClaimParams {
+            owner,
+            token_contract_address: TOKEN,
+        });
+        let mut logger = TestLogger::init();
+        transact(host, |host| claim_reward(&receive_ctx(owner, now, &parameter), host, &mut logger))?;
+        Ok(logger)
+    }
+
+    fn fund(host: &mut Host, amount: u64) {
+        let parameter = to_bytes(&FundRewardPoolParams {
+            amount,
+            token_contract_address: TOKEN,
+        });
+        transact(host, |host| fund_reward_pool(&receive_ctx(ADMIN, START, &parameter), host))
+            .expect("funding the reward pool");
+    }
+
+    fn pending_reward(host: &Host, owner: AccountAddress, now: u64) -> u64 {
+        let parameter = to_bytes(&owner);
+        view_pending_reward(&receive_ctx(owner, now, &parameter), host).expect("viewing the pending reward")
+    }
+
+    #[test]
+    fn one_year_of_millis_accrues_the_full_amount_at_full_rate() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        assert_eq!(host.state().elapsed_seconds(START, START + YEAR_MILLIS), *SECOND_PER_YEAR);
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 1_000);
+    }
+
+    #[test]
+    fn elapsed_time_is_not_off_by_a_factor_of_1000() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000_000, START).expect("staking");
+
+        // A thousandth of a year, read as seconds instead of milliseconds,
+        // would pay a thousand times too much or too little.
+        assert_eq!(host.state().elapsed_seconds(START, START + YEAR_MILLIS / 1000), *SECOND_PER_YEAR / 1000);
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS / 1000), 1_000);
+        // Less than a second accrues nothing rather than a second's worth per
+        // millisecond.
+        assert_eq!(host.state().elapsed_seconds(START, START + 999), 0);
+        assert_eq!(pending_reward(&host, ALICE, START + 999), 0);
+        assert_eq!(host.state().elapsed_seconds(START, START + 1_000), 1);
+    }
+
+    #[test]
+    fn claim_after_one_year_transfers_the_full_amount() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+    }
+
+    #[test]
+    fn claim_above_the_cap_pays_only_the_cap() {
+        let (mut host, transfers) = setup(InitParams {
+            max_reward_per_tx: Some(100),
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let logger = claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![100]);
+        assert!(logger.logs.contains(&logged(&StakingEvent::RewardClamped {
+            owner:    ALICE,
+            computed: 1_000,
+            paid:     100,
+        })));
+        assert_eq!(host.state().reward_pool, 9_900);
+    }
+
+    #[test]
+    fn unstake_above_the_cap_forfeits_the_excess_like_claim() {
+        let (mut host, transfers) = setup(InitParams {
+            max_reward_per_tx: Some(100),
+            residual_claim_window: 24 * 60 * 60 * 1000,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let logger = unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![100, 1_000]);
+        assert!(logger.logs.contains(&logged(&StakingEvent::RewardClamped {
+            owner:    ALICE,
+            computed: 1_000,
+            paid:     100,
+        })));
+        // Nothing above the cap is kept owed, as residual or otherwise.
+        assert!(host.state().pending_after_unstake.get(&ALICE).is_none());
+        assert_eq!(host.state().total_liabilities, 0);
+        assert_eq!(host.state().reward_pool, 9_900);
+    }
+
+    #[test]
+    fn void_transfer_accepts_an_empty_response() {
+        let (mut host, transfers) = setup(init_params());
+
+        let result = Cis2Client::transfer(
+            &mut host,
+            TOKEN_ID,
+            TOKEN,
+            TokenAmountU64(5),
+            Address::Contract(SELF_ADDRESS),
+            Receiver::Account(ALICE),
+            AdditionalData::empty(),
+        );
+
+        assert_eq!(result, Ok(true));
+        assert_eq!(paid_to(&transfers, ALICE), vec![5]);
+    }
+
+    #[test]
+    fn query_rejects_an_empty_response() {
+        let (mut host, _) = setup(init_params());
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(BALANCE_OF_ENTRYPOINT_NAME.into()),
+            MockFn::new_v0::<(), _>(|_, _, _, _| Ok(false)),
+        );
+
+        let result = Cis2Client::balance_of(&host, TOKEN_ID, &TOKEN, Address::Account(ALICE));
+
+        assert_eq!(result, Err(Cis2ClientError::InvokeContractError));
+    }
+
+    /// Alice fully unstakes a one-year stake of 1000 at `START + YEAR_MILLIS`
+    /// while the reward pool is empty, leaving the reward of 1000 as residual
+    /// claimable for a day. The pool is funded afterwards.
+    fn setup_residual() -> (Host, Transfers) {
+        let (mut host, transfers) = setup(InitParams {
+            residual_claim_window: 24 * 60 * 60 * 1000,
+            ..init_params()
+        });
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking");
+        fund(&mut host, 10_000);
+        (host, transfers)
+    }
+
+    fn claim_residual_at(host: &mut Host, owner: AccountAddress, now: u64) -> ContractResult<()> {
+        let parameter = to_bytes(&ClaimParams {
+            owner,
+            token_contract_address: TOKEN,
+        });
+        transact(host, |host| {
+            claim_residual(&receive_ctx(owner, now, &parameter), host, &mut TestLogger::init())
+        })
+    }
+
+    #[test]
+    fn residual_is_claimable_within_the_window() {
+        let (mut host, transfers) = setup_residual();
+        // The unstake paid no reward and returned the principal.
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+
+        claim_residual_at(&mut host, ALICE, START + YEAR_MILLIS + 1_000).expect("claiming the residual");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000, 1_000]);
+        assert!(host.state().pending_after_unstake.get(&ALICE).is_none());
+    }
+
+    #[test]
+    fn residual_is_forfeited_after_the_window() {
+        let (mut host, transfers) = setup_residual();
+
+        let result = claim_residual_at(&mut host, ALICE, START + YEAR_MILLIS + 24 * 60 * 60 * 1000);
+
+        assert_eq!(result, Err(CustomContractError::ResidualExpired.into()));
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+        assert_eq!(host.state().reward_pool, 10_000);
+    }
+
+    #[test]
+    fn stake_spanning_a_halving_earns_both_rates() {
+        let (mut host, _) = setup(InitParams {
+            halving_interval_seconds: Some(*SECOND_PER_YEAR),
+            ..init_params()
+        });
+        stake(&mut host, ALICE, 1_000, START + YEAR_MILLIS / 2).expect("staking");
+
+        // Half a year at the full rate, then half a year at half the rate.
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 500);
+        assert_eq!(pending_reward(&host, ALICE, START + 3 * YEAR_MILLIS / 2), 750);
+    }
+
+    #[test]
+    fn stake_overlapping_a_no_accrual_window_skips_it() {
+        let (mut host, _) = setup(InitParams {
+            no_accrual_windows: vec![(START + YEAR_MILLIS / 4, START + YEAR_MILLIS / 2)],
+            ..init_params()
+        });
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS / 2), 250);
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 750);
+    }
+
+    #[test]
+    fn stake_overlapping_two_no_accrual_windows_skips_both() {
+        let (mut host, _) = setup(InitParams {
+            no_accrual_windows: vec![
+                (START + YEAR_MILLIS / 4, START + YEAR_MILLIS / 2),
+                (START + 3 * YEAR_MILLIS / 4, START + 7 * YEAR_MILLIS / 8),
+            ],
+            ..init_params()
+        });
+        // Staked within the first window, so only part of it is skipped.
+        stake(&mut host, ALICE, 1_000, START + 3 * YEAR_MILLIS / 8).expect("staking");
+
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 375);
+    }
+
+    fn init(params: &InitParams) -> ContractResult<State<TestStateApi>> {
+        let parameter = to_bytes(params);
+        let mut ctx = TestInitContext::empty();
+        ctx.set_init_origin(ADMIN)
+            .set_metadata_slot_time(Timestamp::from_timestamp_millis(START))
+            .set_parameter(&parameter);
+        contract_init(&ctx, &mut TestStateBuilder::new())
+    }
+
+    #[test]
+    fn init_rejects_a_zero_time_basis() {
+        let result = init(&InitParams {
+            reward_period_seconds: 0,
+            ..init_params()
+        });
+
+        assert!(matches!(result, Err(Cis2Error::Custom(CustomContractError::ZeroTimeBasis))));
+        assert!(init(&init_params()).is_ok());
+    }
+
+    /// Account of the `index`th of many stakers.
+    fn staker(index: u16) -> AccountAddress {
+        let mut address = [0xffu8; 32];
+        address[..2].copy_from_slice(&index.to_le_bytes());
+        AccountAddress(address)
+    }
+
+    /// The test host does not meter energy, so the bound is asserted on what
+    /// the energy of the iterating entrypoints grows with: the number of
+    /// positions a single call visits. Each is capped by a documented
+    /// constant however many stakers there are.
+    #[test]
+    fn iterating_entrypoints_visit_a_bounded_number_of_positions() {
+        let (mut host, _) = setup(init_params());
+        let stakers = (MAX_BUCKET_SCAN + 1) as u16;
+        for index in 0..stakers {
+            host.state_mut().insert_token(&staker(index), 1, START, None).expect("inserting a position");
+        }
+
+        let parameter = to_bytes(&0u64);
+        let buckets = view_stake_age_buckets(&receive_ctx(ALICE, START, &parameter), &host).expect("bucketing");
+        assert_eq!(buckets.next, Some(MAX_BUCKET_SCAN as u64));
+        assert_eq!(buckets.buckets.iter().map(|bucket| bucket.count).sum::<u64>(), MAX_BUCKET_SCAN as u64);
+
+        let next = force_settle_all(&receive_ctx(ADMIN, START + 1_000, &parameter), &mut host).expect("settling");
+        assert_eq!(next, Some(MAX_SETTLE_BATCH as u64));
+
+        let parameter = to_bytes(&ListStakersParams {
+            start: 0,
+            limit: MAX_LIST_STAKERS + 1,
+        });
+        let result = list_stakers(&receive_ctx(ALICE, START, &parameter), &host);
+        assert_eq!(result.err(), Some(CustomContractError::LimitTooLarge.into()));
+    }
+
+    /// Claiming and unstaking do not sum up the reward owed to every other
+    /// position, which would make their energy grow with the number of
+    /// stakers, but still share the pool with the reward those positions
+    /// accrued without being settled.
+    #[test]
+    fn position_entrypoints_do_not_visit_other_positions() {
+        let (mut host, transfers) = setup(InitParams {
+            shortfall_policy: ShortfallPolicy::ProRata,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        for index in 0..1_000 {
+            host.state_mut().insert_token(&staker(index), 1_000, START, None).expect("inserting a position");
+        }
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+        unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking");
+
+        // The pool covers 10000 of the 1001000 owed to all positions.
+        assert_eq!(paid_to(&transfers, ALICE), vec![9, 1_000]);
+        assert_eq!(host.state().total_liabilities, 0);
+        assert_eq!(host.state().liabilities_at(START + YEAR_MILLIS), 1_000_000);
+    }
+
+    #[test]
+    fn split_reward_compounds_60_and_pays_out_40_percent() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let parameter = to_bytes(&SplitRewardParams {
+            owner:                  ALICE,
+            compound_amount:        600,
+            token_contract_address: TOKEN,
+        });
+        let mut logger = TestLogger::init();
+        split_reward(&receive_ctx(ALICE, START + YEAR_MILLIS, &parameter), &mut host, &mut logger)
+            .expect("splitting the reward");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![400]);
+        let state = host.state();
+        assert_eq!(state.stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_600));
+        assert_eq!(state.total_staked, 1_600);
+        assert_eq!(state.held_principal, 1_600);
+        assert_eq!(state.reward_pool, 9_000);
+        assert_eq!(logger.logs, vec![
+            logged(&StakingEvent::Compounded {
+                owner:  ALICE,
+                amount: 600,
+            }),
+            logged(&StakingEvent::Claimed {
+                owner:  ALICE,
+                reward: 400,
+            }),
+        ]);
+    }
+
+    /// Calls an entrypoint with the context, discarding what it returns.
+    type Call = fn(&TestReceiveContext, &mut Host) -> ContractResult<()>;
+
+    /// A well-formed parameter for each staker entrypoint, with the
+    /// entrypoint it is for.
+    fn entrypoint_calls() -> Vec<(&'static str, Vec<u8>, Call)> {
+        let stake_params = StakeParams {
+            owner:                  ALICE,
+            amount:                 1,
+            token_contract_address: TOKEN,
+            idempotency_key:        Some(7),
+            referrer:               Some(TREASURY),
+        };
+        let stake_for_params = StakeForParams {
+            owner:                  ALICE,
+            amount:                 1,
+            token_contract_address: TOKEN,
+        };
+        let claim_params = ClaimParams {
+            owner:                  ALICE,
+            token_contract_address: TOKEN,
+        };
+        let lot_params = UnstakeLotParams {
+            owner:                  ALICE,
+            lot_id:                 0,
+            token_contract_address: TOKEN,
+        };
+        vec![
+            ("stake", to_bytes(&stake_params), |ctx, host| stake_token(ctx, host, &mut TestLogger::init())),
+            ("stakeFor", to_bytes(&stake_for_params), |ctx, host| stake_for(ctx, host, &mut TestLogger::init())),
+            ("batchStake", to_bytes(&vec![stake_for_params]), |ctx, host| {
+                batch_stake(ctx, host, &mut TestLogger::init())
+            }),
+            (
+                "unstake",
+                to_bytes(&UnStakeParams {
+                    owner:                  ALICE,
+                    token_contract_address: TOKEN,
+                    amount:                 1,
+                }),
+                |ctx, host| unstake_token(ctx, host, &mut TestLogger::init()),
+            ),
+            ("emergencyWithdraw", to_bytes(&TOKEN), |ctx, host| {
+                emergency_withdraw(ctx, host, &mut TestLogger::init())
+            }),
+            (
+                "requestUnstake",
+                to_bytes(&RequestUnstakeParams {
+                    owner:  ALICE,
+                    amount: 1,
+                }),
+                |ctx, host| request_unstake(ctx, host),
+            ),
+            (
+                "withdraw",
+                to_bytes(&WithdrawParams {
+                    owner:                  ALICE,
+                    token_contract_address: TOKEN,
+                }),
+                |ctx, host| withdraw(ctx, host, &mut TestLogger::init()),
+            ),
+            (
+                "stakeNewLot",
+                to_bytes(&StakeNewLotParams {
+                    owner:                  ALICE,
+                    amount:                 1,
+                    lock_seconds:           0,
+                    token_contract_address: TOKEN,
+                }),
+                |ctx, host| stake_new_lot(ctx, host, &mut TestLogger::init()).map(|_| ()),
+            ),
+            ("unstakeLot", to_bytes(&lot_params), |ctx, host| unstake_lot(ctx, host, &mut TestLogger::init())),
+            ("withdrawLot", to_bytes(&lot_params), |ctx, host| withdraw_lot(ctx, host, &mut TestLogger::init())),
+            ("claim", to_bytes(&claim_params), |ctx, host| claim_reward(ctx, host, &mut TestLogger::init())),
+            ("claimResidual", to_bytes(&claim_params), |ctx, host| {
+                claim_residual(ctx, host, &mut TestLogger::init())
+            }),
+            ("claimReferralReward", to_bytes(&TOKEN), |ctx, host| {
+                claim_referral_reward(ctx, host, &mut TestLogger::init())
+            }),
+            (
+                "splitReward",
+                to_bytes(&SplitRewardParams {
+                    owner:                  ALICE,
+                    compound_amount:        0,
+                    token_contract_address: TOKEN,
+                }),
+                |ctx, host| split_reward(ctx, host, &mut TestLogger::init()),
+            ),
+            (
+                "offerPosition",
+                to_bytes(&OfferPositionParams {
+                    to:         BOB,
+                    expires_at: START,
+                }),
+                |ctx, host| offer_position(ctx, host),
+            ),
+            ("acceptPosition", to_bytes(&BOB), |ctx, host| accept_position(ctx, host)),
+            (
+                "fundRewardPool",
+                to_bytes(&FundRewardPoolParams {
+                    amount:                 1,
+                    token_contract_address: TOKEN,
+                }),
+                |ctx, host| fund_reward_pool(ctx, host),
+            ),
+            ("viewPendingReward", to_bytes(&ALICE), |ctx, host| view_pending_reward(ctx, host).map(|_| ())),
+            ("viewTiming", to_bytes(&ALICE), |ctx, host| view_timing(ctx, host).map(|_| ())),
+            (
+                "listStakers",
+                to_bytes(&ListStakersParams {
+                    start: 0,
+                    limit: 1,
+                }),
+                |ctx, host| list_stakers(ctx, host).map(|_| ()),
+            ),
+        ]
+    }
+
+    #[test]
+    fn truncated_parameters_are_rejected_with_parse_params() {
+        for (name, parameter, call) in entrypoint_calls() {
+            let (mut host, _) = setup(init_params());
+            for len in 0..parameter.len() {
+                let result = call(&receive_ctx(ALICE, START, &parameter[..len]), &mut host);
+                assert_eq!(result, Err(CustomContractError::ParseParams.into()), "{} with {} bytes", name, len);
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_parameters_are_rejected_with_parse_params() {
+        for (name, mut parameter, call) in entrypoint_calls() {
+            let (mut host, _) = setup(init_params());
+            parameter.push(0);
+            let result = call(&receive_ctx(ALICE, START, &parameter), &mut host);
+            assert_eq!(result, Err(CustomContractError::ParseParams.into()), "{} with a trailing byte", name);
+        }
+    }
+
+    #[test]
+    fn batch_above_the_maximum_is_rejected_from_its_length_prefix() {
+        let (mut host, _) = setup(init_params());
+        // Only the length prefix: the entries are never parsed.
+        let parameter = to_bytes(&(MAX_BATCH_STAKE as u32 + 1));
+
+        let result = batch_stake(&receive_ctx(ALICE, START, &parameter), &mut host, &mut TestLogger::init());
+
+        assert_eq!(result, Err(CustomContractError::LimitTooLarge.into()));
+    }
+
+    #[test]
+    fn claims_beyond_the_block_limit_are_throttled() {
+        let (mut host, _) = setup(InitParams {
+            max_claims_per_block: Some(2),
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        for owner in [ALICE, BOB, TREASURY] {
+            stake(&mut host, owner, 1_000, START).expect("staking");
+        }
+
+        let block = START + YEAR_MILLIS;
+        claim(&mut host, ALICE, block).expect("first claim in the block");
+        claim(&mut host, BOB, block).expect("second claim in the block");
+        assert_eq!(claim(&mut host, TREASURY, block).err(), Some(CustomContractError::ClaimThrottled.into()));
+        // The next block starts a new count.
+        claim(&mut host, TREASURY, block + 1).expect("claim in the next block");
+    }
+
+    #[test]
+    fn stakes_move_the_total_by_their_amount() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, BOB, 1_000, START).expect("staking");
+        stake(&mut host, ALICE, 500, START).expect("staking");
+        stake(&mut host, ALICE, 250, START).expect("topping up");
+
+        assert_eq!(host.state().total_staked, 1_750);
+    }
+
+    /// The overwrite bug set `total_staked` to the amount of the position
+    /// being staked into, dropping everyone else's stake.
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "total_staked drifted from the position amounts"))]
+    fn total_overwritten_with_the_position_amount_is_caught() {
+        let result = ensure_total_delta(1_000, 500, 0, 500);
+
+        assert_eq!(result, Err(CustomContractError::StateInconsistent.into()));
+    }
+
+    /// Alice unstakes a one-year stake of 1000 under a 1% unstake fee routed
+    /// to `fee_destination`, with 10_000 in the reward pool.
+    fn unstake_with_fee(fee_destination: FeeDestination) -> (Host, Transfers, TestLogger) {
+        let (mut host, transfers) = setup(InitParams {
+            unstake_fee_bps: 100,
+            fee_destination,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let logger = unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking");
+        (host, transfers, logger)
+    }
+
+    #[test]
+    fn unstake_fee_routed_to_the_pool_increases_it() {
+        let (host, transfers, logger) = unstake_with_fee(FeeDestination::RewardPool);
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000, 990]);
+        assert!(paid_to(&transfers, TREASURY).is_empty());
+        assert_eq!(host.state().reward_pool, 9_010);
+        assert_eq!(host.state().held_principal, 0);
+        assert!(logger.logs.contains(&logged(&StakingEvent::UnstakeFeeCharged {
+            owner:       ALICE,
+            amount:      10,
+            destination: FeeDestination::RewardPool,
+        })));
+    }
+
+    #[test]
+    fn unstake_fee_routed_to_the_treasury_is_transferred_out() {
+        let (host, transfers, logger) = unstake_with_fee(FeeDestination::Treasury);
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000, 990]);
+        assert_eq!(paid_to(&transfers, TREASURY), vec![10]);
+        assert_eq!(host.state().reward_pool, 9_000);
+        assert_eq!(host.state().held_principal, 0);
+        assert!(logger.logs.contains(&logged(&StakingEvent::UnstakeFeeCharged {
+            owner:       ALICE,
+            amount:      10,
+            destination: FeeDestination::Treasury,
+        })));
+    }
+
+    /// Alice and Bob are each owed 1000 of reward, settled, while the reward
+    /// pool holds 1000 under `shortfall_policy`.
+    fn setup_shortfall(shortfall_policy: ShortfallPolicy) -> (Host, Transfers) {
+        let (mut host, transfers) = setup(InitParams {
+            shortfall_policy,
+            ..init_params()
+        });
+        fund(&mut host, 1_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        stake(&mut host, BOB, 1_000, START).expect("staking");
+        let parameter = to_bytes(&0u64);
+        force_settle_all(&receive_ctx(ADMIN, START + YEAR_MILLIS, &parameter), &mut host).expect("settling");
+        assert_eq!(host.state().total_liabilities, 2_000);
+        (host, transfers)
+    }
+
+    #[test]
+    fn pro_rata_pays_each_claimer_the_pool_coverage() {
+        let (mut host, transfers) = setup_shortfall(ShortfallPolicy::ProRata);
+
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+        claim(&mut host, BOB, START + YEAR_MILLIS).expect("claiming");
+
+        // The pool covers half of what is owed, so each gets half.
+        assert_eq!(paid_to(&transfers, ALICE), vec![500]);
+        assert_eq!(paid_to(&transfers, BOB), vec![500]);
+        assert_eq!(host.state().reward_pool, 0);
+        assert_eq!(host.state().total_liabilities, 0);
+    }
+
+    #[test]
+    fn pro_rata_counts_the_unsettled_reward_of_other_positions() {
+        let (mut host, transfers) = setup(InitParams {
+            shortfall_policy: ShortfallPolicy::ProRata,
+            ..init_params()
+        });
+        fund(&mut host, 1_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        stake(&mut host, BOB, 1_000, START).expect("staking");
+        assert_eq!(host.state().liabilities_at(START + YEAR_MILLIS), 2_000);
+
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+        claim(&mut host, BOB, START + YEAR_MILLIS).expect("claiming");
+
+        // Bob's reward was never settled before Alice claimed, yet she only
+        // gets the pool's coverage of what both are owed.
+        assert_eq!(paid_to(&transfers, ALICE), vec![500]);
+        assert_eq!(paid_to(&transfers, BOB), vec![500]);
+        assert_eq!(host.state().reward_pool, 0);
+        assert_eq!(host.state().liabilities_at(START + YEAR_MILLIS), 0);
+    }
+
+    #[test]
+    fn first_come_first_served_keeps_the_uncovered_reward_owed() {
+        let (mut host, transfers) = setup_shortfall(ShortfallPolicy::FirstComeFirstServed);
+
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+        claim(&mut host, BOB, START + YEAR_MILLIS).expect("claiming");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+        assert_eq!(paid_to(&transfers, BOB), Vec::<u64>::new());
+        assert_eq!(host.state().stake.get(&BOB).map(|owner_state| owner_state.accrued), Some(1_000));
+        assert_eq!(host.state().total_liabilities, 1_000);
+    }
+
+    #[test]
+    fn stake_is_callable_end_to_end() {
+        let (mut host, transfers) = setup(init_params());
+
+        let logger = stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let state = host.state();
+        assert_eq!(state.stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+        assert_eq!(state.total_staked, 1_000);
+        assert_eq!(state.staker_count, 1);
+        assert_eq!(state.held_principal, 1_000);
+        assert!(matches!(
+            transfers.borrow().as_slice(),
+            [(Address::Account(ALICE), Receiver::Contract(SELF_ADDRESS, _), 1_000)]
+        ));
+        assert_eq!(logger.logs, vec![logged(&StakingEvent::Staked {
+            owner:  ALICE,
+            amount: 1_000,
+        })]);
+    }
+
+    #[test]
+    fn stake_without_custody_leaves_the_principal_in_the_wallet() {
+        let (mut host, transfers) = setup(InitParams {
+            custody: false,
+            ..init_params()
+        });
+
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        assert_eq!(host.state().total_staked, 1_000);
+        assert_eq!(host.state().held_principal, 0);
+        assert!(transfers.borrow().is_empty());
+    }
+
+    #[test]
+    fn claim_pays_the_reward_and_keeps_the_principal_staked() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let logger = claim(&mut host, ALICE, START + YEAR_MILLIS / 2).expect("claiming");
+
+        let state = host.state();
+        let owner_state = state.stake.get(&ALICE).expect("position kept");
+        assert_eq!(owner_state.amount, 1_000);
+        assert_eq!(owner_state.last_claimed_at, START + YEAR_MILLIS / 2);
+        assert_eq!(state.total_staked, 1_000);
+        assert_eq!(state.held_principal, 1_000);
+        assert_eq!(paid_to(&transfers, ALICE), vec![500]);
+        assert_eq!(logger.logs, vec![logged(&StakingEvent::Claimed {
+            owner:  ALICE,
+            reward: 500,
+        })]);
+        // Accrual restarts from the claim.
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 500);
+    }
+
+    /// Restores the slot time when a test using the injected clock ends,
+    /// however it ends.
+    struct TestClock;
+
+    impl TestClock {
+        fn set(now: u64) -> Self {
+            set_test_clock(Some(now));
+            TestClock
+        }
+    }
+
+    impl Drop for TestClock {
+        fn drop(&mut self) { set_test_clock(None); }
+    }
+
+    #[test]
+    fn injected_clock_overrides_the_slot_time() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        let clock = TestClock::set(START);
+        stake(&mut host, ALICE, 1_000, START + 5 * YEAR_MILLIS).expect("staking");
+
+        // The slot time of the calls is ignored in favour of the clock.
+        set_test_clock(Some(START + YEAR_MILLIS / 4));
+        assert_eq!(pending_reward(&host, ALICE, START), 250);
+        set_test_clock(Some(START + YEAR_MILLIS));
+        assert_eq!(pending_reward(&host, ALICE, START), 1_000);
+        claim(&mut host, ALICE, START).expect("claiming");
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+
+        drop(clock);
+        assert_eq!(current_time(&receive_ctx(ALICE, START + 7, &[])), START + 7);
+    }
+
+    #[test]
+    fn partial_unstake_leaves_the_rest_accruing() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        unstake(&mut host, ALICE, 400, START + YEAR_MILLIS / 2).expect("unstaking");
+
+        // The whole accrued reward is paid out with the withdrawn principal.
+        assert_eq!(paid_to(&transfers, ALICE), vec![500, 400]);
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(600));
+        assert_eq!(host.state().total_staked, 600);
+        assert_eq!(host.state().staker_count, 1);
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 300);
+    }
+
+    #[test]
+    fn unstaking_more_than_staked_is_rejected() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let result = unstake(&mut host, ALICE, 1_001, START + YEAR_MILLIS);
+
+        assert_eq!(result.err(), Some(CustomContractError::NoBalance.into()));
+        assert_eq!(host.state().total_staked, 1_000);
+        // Only the funding and the stake transferred anything.
+        assert_eq!(transfers.borrow().len(), 2);
+    }
+
+    #[test]
+    fn unstaking_nothing_is_rejected() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let result = unstake(&mut host, ALICE, 0, START + YEAR_MILLIS);
+
+        assert_eq!(result.err(), Some(CustomContractError::InvalidUnstakeAmount.into()));
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 1_000);
+    }
+
+    const COOLDOWN_MILLIS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+    fn setup_cooldown() -> (Host, Transfers) {
+        let (mut host, transfers) = setup(InitParams {
+            cooldown_seconds: COOLDOWN_MILLIS / 1000,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        (host, transfers)
+    }
+
+    fn request_unstake_at(host: &mut Host, owner: AccountAddress, amount: u64, now: u64) -> ContractResult<()> {
+        let parameter = to_bytes(&RequestUnstakeParams {
+            owner,
+            amount,
+        });
+        transact(host, |host| request_unstake(&receive_ctx(owner, now, &parameter), host))
+    }
+
+    fn withdraw_at(host: &mut Host, owner: AccountAddress, now: u64) -> ContractResult<()> {
+        let parameter = to_bytes(&WithdrawParams {
+            owner,
+            token_contract_address: TOKEN,
+        });
+        transact(host, |host| withdraw(&receive_ctx(owner, now, &parameter), host, &mut TestLogger::init()))
+    }
+
+    #[test]
+    fn withdraw_succeeds_only_after_the_cooldown() {
+        let (mut host, transfers) = setup_cooldown();
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        assert_eq!(
+            unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).err(),
+            Some(CustomContractError::CoolingDown.into())
+        );
+
+        request_unstake_at(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("requesting the unstake");
+        let amounts =
+            host.state().stake.get(&ALICE).map(|owner_state| (owner_state.amount, owner_state.unbonding_amount));
+        assert_eq!(amounts, Some((0, 1_000)));
+        assert_eq!(host.state().total_staked, 0);
+        // Unbonding principal stops accruing; what accrued before stays.
+        assert_eq!(pending_reward(&host, ALICE, START + 2 * YEAR_MILLIS), 1_000);
+
+        let result = withdraw_at(&mut host, ALICE, START + YEAR_MILLIS + COOLDOWN_MILLIS - 1);
+        assert_eq!(result, Err(CustomContractError::CoolingDown.into()));
+        assert!(paid_to(&transfers, ALICE).is_empty());
+
+        withdraw_at(&mut host, ALICE, START + YEAR_MILLIS + COOLDOWN_MILLIS).expect("withdrawing");
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+        assert_eq!(host.state().held_principal, 0);
+        // The accrued reward keeps the position open until it is claimed.
+        claim(&mut host, ALICE, START + 2 * YEAR_MILLIS).expect("claiming");
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000, 1_000]);
+        assert!(host.state().stake.get(&ALICE).is_none());
+    }
+
+    #[test]
+    fn unstaked_lots_go_through_the_cooldown() {
+        let (mut host, transfers) = setup_cooldown();
+        let parameter = to_bytes(&StakeNewLotParams {
+            owner:                  ALICE,
+            amount:                 500,
+            lock_seconds:           0,
+            token_contract_address: TOKEN,
+        });
+        let lot_id = transact(&mut host, |host| {
+            stake_new_lot(&receive_ctx(ALICE, START, &parameter), host, &mut TestLogger::init())
+        })
+        .expect("staking a lot");
+        let parameter = to_bytes(&UnstakeLotParams {
+            owner: ALICE,
+            lot_id,
+            token_contract_address: TOKEN,
+        });
+
+        transact(&mut host, |host| {
+            unstake_lot(&receive_ctx(ALICE, START + YEAR_MILLIS, &parameter), host, &mut TestLogger::init())
+        })
+        .expect("unstaking the lot");
+        // Only the reward is paid out; the principal cools down.
+        assert_eq!(paid_to(&transfers, ALICE), vec![500]);
+        assert_eq!(host.state().total_staked, 0);
+
+        let withdraw_lot_at = |host: &mut Host, now| {
+            transact(host, |host| {
+                withdraw_lot(&receive_ctx(ALICE, now, &parameter), host, &mut TestLogger::init())
+            })
+        };
+        let result = withdraw_lot_at(&mut host, START + YEAR_MILLIS + COOLDOWN_MILLIS - 1);
+        assert_eq!(result, Err(CustomContractError::CoolingDown.into()));
+        withdraw_lot_at(&mut host, START + YEAR_MILLIS + COOLDOWN_MILLIS).expect("withdrawing the lot");
+        assert_eq!(paid_to(&transfers, ALICE), vec![500, 500]);
+        assert_eq!(host.state().held_principal, 0);
+    }
+
+    #[test]
+    fn accepting_onto_an_unbonding_position_is_rejected() {
+        let (mut host, _) = setup_cooldown();
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        stake(&mut host, BOB, 300, START).expect("staking");
+        request_unstake_at(&mut host, BOB, 300, START).expect("requesting the unstake");
+        let parameter = to_bytes(&OfferPositionParams {
+            to:         BOB,
+            expires_at: START + YEAR_MILLIS,
+        });
+        offer_position(&receive_ctx(ALICE, START, &parameter), &mut host).expect("offering the position");
+
+        let parameter = to_bytes(&ALICE);
+        let result = transact(&mut host, |host| accept_position(&receive_ctx(BOB, START, &parameter), host));
+
+        assert_eq!(result, Err(CustomContractError::RecipientHasPosition.into()));
+        let state = host.state();
+        assert_eq!(state.stake.get(&BOB).map(|owner_state| owner_state.unbonding_amount), Some(300));
+        assert_eq!(state.stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+    }
+
+    #[test]
+    fn one_year_stake_is_exactly_a_year_of_seconds() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let parameter = to_bytes(&ALICE);
+        let timing = view_timing(&receive_ctx(ALICE, START + YEAR_MILLIS, &parameter), &host).expect("viewing");
+
+        assert_eq!(timing.staked_start_at_millis, START);
+        assert_eq!(timing.now_millis, START + YEAR_MILLIS);
+        assert_eq!(timing.elapsed_seconds, *SECOND_PER_YEAR);
+        assert_eq!(host.state().get_time(&ALICE, START + YEAR_MILLIS - 1), Ok(*SECOND_PER_YEAR - 1));
+    }
+
+    #[test]
+    fn reentrant_unstake_during_a_transfer_is_rejected() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        // A malicious token contract trying to call back into the staking
+        // contract from its `transfer`. The mock only gets the state, so it
+        // makes the reentrancy check a callback would run first.
+        let reentered: Rc<RefCell<Vec<ContractResult<()>>>> = Rc::default();
+        let attempts = reentered.clone();
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(TRANSFER_ENTRYPOINT_NAME.into()),
+            MockFn::new_v0::<(), _>(move |_, _, _, state: &mut State<TestStateApi>| {
+                attempts.borrow_mut().push(state.enter_call());
+                Ok(false)
+            }),
+        );
+
+        unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking");
+
+        // The reward and the principal transfer each attempted a callback.
+        assert_eq!(*reentered.borrow(), vec![
+            Err(CustomContractError::Reentrancy.into()),
+            Err(CustomContractError::Reentrancy.into()),
+        ]);
+        assert!(!host.state().locked);
+    }
+
+    #[test]
+    fn unstake_while_locked_is_rejected() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        // The state a callback from the token contract sees.
+        host.state_mut().locked = true;
+        host.commit_state();
+
+        let result = unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS);
+
+        assert_eq!(result.err(), Some(CustomContractError::Reentrancy.into()));
+        assert_eq!(host.state().total_staked, 1_000);
+    }
+
+    #[test]
+    fn claim_keeps_the_position_busy_across_the_payout() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let statuses: Rc<RefCell<Vec<Option<PositionStatus>>>> = Rc::default();
+        let observed = statuses.clone();
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(TRANSFER_ENTRYPOINT_NAME.into()),
+            MockFn::new_v0::<(), _>(move |_, _, _, state: &mut State<TestStateApi>| {
+                observed.borrow_mut().push(state.stake.get(&ALICE).map(|owner_state| owner_state.status));
+                Ok(false)
+            }),
+        );
+
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+
+        assert!(*statuses.borrow() == vec![Some(PositionStatus::Claiming)]);
+        let status = host.state().stake.get(&ALICE).map(|owner_state| owner_state.status);
+        assert!(status == Some(PositionStatus::Active));
+    }
+
+    #[test]
+    fn failed_payout_reverts_the_claim() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(TRANSFER_ENTRYPOINT_NAME.into()),
+            MockFn::returning_err::<()>(CallContractError::Trap),
+        );
+
+        let result = claim(&mut host, ALICE, START + YEAR_MILLIS);
+
+        assert_eq!(
+            result.err(),
+            Some(CustomContractError::Cis2ClientError(Cis2ClientError::InvokeContractError).into())
+        );
+        let state = host.state();
+        assert!(!state.locked);
+        assert_eq!(state.reward_pool, 10_000);
+        assert!(state.stake.get(&ALICE).is_some_and(|owner_state| owner_state.status == PositionStatus::Active));
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 1_000);
+    }
+
+    #[test]
+    fn claim_without_a_position_is_rejected_without_creating_one() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+
+        let result = claim(&mut host, ALICE, START);
+
+        assert_eq!(result.err(), Some(CustomContractError::TokenNotFound.into()));
+        assert!(host.state().stake.get(&ALICE).is_none());
+        assert!(!host.state().locked);
+    }
+
+    #[test]
+    fn rate_change_applies_only_from_the_change_on() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        stake(&mut host, BOB, 1_000, START + YEAR_MILLIS / 2).expect("staking");
+
+        let parameter = to_bytes(&5_000u16);
+        set_reward_rate(&receive_ctx(ADMIN, START + YEAR_MILLIS / 2, &parameter), &mut host)
+            .expect("setting the rate");
+
+        // Half a year at the full rate, then half a year at half the rate.
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 750);
+        assert_eq!(pending_reward(&host, BOB, START + YEAR_MILLIS), 250);
+        // No position was settled to make the change.
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.accrued), Some(0));
+    }
+
+    #[test]
+    fn tier_reached_mid_period_applies_from_that_time_on() {
+        let (mut host, transfers) = setup(InitParams {
+            apr_tiers: vec![(0, 1_000), (*SECOND_PER_YEAR / 2, 10_000)],
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        stake(&mut host, BOB, 1_000, START).expect("staking");
+
+        // Half a year in the lower tier, then half a year in the upper one,
+        // whether or not the position was claimed in between.
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 550);
+        claim(&mut host, BOB, START + YEAR_MILLIS / 2).expect("claiming");
+        claim(&mut host, BOB, START + YEAR_MILLIS).expect("claiming");
+        assert_eq!(paid_to(&transfers, BOB), vec![50, 500]);
+    }
+
+    #[test]
+    fn unpaid_exit_reward_stays_accrued_without_a_residual_window() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 400);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let logger = unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![400, 1_000]);
+        assert!(logger.logs.contains(&logged(&StakingEvent::RewardShortfall {
+            owner:  ALICE,
+            amount: 600,
+        })));
+        let owed = host.state().stake.get(&ALICE).map(|owner_state| (owner_state.amount, owner_state.accrued));
+        assert_eq!(owed, Some((0, 600)));
+        assert_eq!(host.state().staker_count, 0);
+
+        fund(&mut host, 1_000);
+        claim(&mut host, ALICE, START + 2 * YEAR_MILLIS).expect("claiming");
+        assert_eq!(paid_to(&transfers, ALICE), vec![400, 1_000, 600]);
+        assert!(host.state().stake.get(&ALICE).is_none());
+    }
+
+    #[test]
+    fn unpaid_exit_reward_is_not_reclaimable_right_after_the_exit() {
+        let (mut host, _) = setup(InitParams {
+            reclaim_after_seconds: Some(30 * 24 * 60 * 60),
+            ..init_params()
+        });
+        fund(&mut host, 400);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking");
+
+        let parameter = to_bytes(&ReclaimUnclaimedParams {
+            owner: ALICE,
+        });
+        let result = reclaim_unclaimed_after(
+            &receive_ctx(ADMIN, START + YEAR_MILLIS + 1_000, &parameter),
+            &mut host,
+            &mut TestLogger::init(),
+        );
+
+        assert_eq!(result, Err(CustomContractError::NotInactive.into()));
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.accrued), Some(600));
+    }
+
+    fn reclaim_at(host: &mut Host, owner: AccountAddress, now: u64) -> ContractResult<TestLogger> {
+        let parameter = to_bytes(&ReclaimUnclaimedParams {
+            owner,
+        });
+        let mut logger = TestLogger::init();
+        transact(host, |host| reclaim_unclaimed_after(&receive_ctx(ADMIN, now, &parameter), host, &mut logger))?;
+        Ok(logger)
+    }
+
+    #[test]
+    fn reclaim_returns_the_reward_of_an_inactive_position_to_the_pool() {
+        let threshold = 30 * 24 * 60 * 60 * 1000;
+        let (mut host, _) = setup(InitParams {
+            reclaim_after_seconds: Some(threshold / 1000),
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let result = reclaim_at(&mut host, ALICE, START + threshold);
+        assert_eq!(result.err(), Some(CustomContractError::NotInactive.into()));
+
+        let logger = reclaim_at(&mut host, ALICE, START + YEAR_MILLIS).expect("reclaiming");
+
+        assert_eq!(logger.logs, vec![logged(&StakingEvent::RewardReclaimed {
+            owner:  ALICE,
+            amount: 1_000,
+        })]);
+        assert_eq!(host.state().reward_pool, 10_000);
+        assert_eq!(host.state().liabilities_at(START + YEAR_MILLIS), 0);
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 0);
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+    }
+
+    #[test]
+    fn top_up_pays_the_reward_after_the_deposit() {
+        let (mut host, transfers) = setup(InitParams {
+            auto_claim_on_top_up: true,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let logger = stake(&mut host, ALICE, 500, START + YEAR_MILLIS).expect("topping up");
+
+        assert!(matches!(
+            &transfers.borrow()[2..],
+            [
+                (Address::Account(ALICE), Receiver::Contract(SELF_ADDRESS, _), 500),
+                (Address::Contract(SELF_ADDRESS), Receiver::Account(ALICE), 1_000),
+            ]
+        ));
+        assert!(logger.logs.contains(&logged(&StakingEvent::Claimed {
+            owner:  ALICE,
+            reward: 1_000,
+        })));
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 0);
+    }
+
+    #[test]
+    fn top_up_while_claims_are_paused_keeps_the_reward_accrued() {
+        let (mut host, transfers) = setup(InitParams {
+            auto_claim_on_top_up: true,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        host.state_mut().pause_flags = PAUSE_CLAIM;
+        host.commit_state();
+
+        stake(&mut host, ALICE, 500, START + YEAR_MILLIS).expect("topping up");
+
+        assert!(paid_to(&transfers, ALICE).is_empty());
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 1_000);
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_500));
+    }
+
+    #[test]
+    fn solvency_ratio_counts_reward_not_yet_settled() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 500);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let ratio_at = |now| solvency_ratio(&receive_ctx(ALICE, now, &[]), &host).expect("viewing the ratio");
+        assert_eq!(ratio_at(START), u64::MAX);
+        // 500 pending half a year in, 1000 a year in.
+        assert_eq!(ratio_at(START + YEAR_MILLIS / 2), 10_000);
+        assert_eq!(ratio_at(START + YEAR_MILLIS), 5_000);
+    }
+
+    #[test]
+    fn collected_dust_equals_the_rounding_loss_of_claims() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 100);
+        stake(&mut host, ALICE, 7, START).expect("staking");
+        // Each half year accrues 3.5, of which the claim pays 3.
+        for half_years in 1..=4 {
+            claim(&mut host, ALICE, START + half_years * YEAR_MILLIS / 2).expect("claiming");
+        }
+        assert_eq!(paid_to(&transfers, ALICE), vec![3, 3, 3, 3]);
+        assert_eq!(host.state().rounding_loss, 2 * host.state().reward_denominator());
+
+        // The contract holds exactly the principal and the reward pool.
+        let balance = host.state().held_principal + host.state().reward_pool;
+        mock_balance(&mut host, balance);
+        let parameter = to_bytes(&TOKEN);
+        let now = START + 2 * YEAR_MILLIS;
+        transact(&mut host, |host| collect_dust(&receive_ctx(ADMIN, now, &parameter), host))
+            .expect("collecting dust");
+
+        assert_eq!(paid_to(&transfers, TREASURY), vec![2]);
+        assert_eq!(host.state().reward_pool, 86);
+        assert_eq!(host.state().rounding_loss, 0);
+    }
+
+    #[test]
+    fn rounding_loss_sums_the_remainders_of_lossy_claims() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let denominator = host.state().reward_denominator();
+        let mut now = START;
+        let mut expected = 0u128;
+        for elapsed in [YEAR_MILLIS / 3 + 1_500, 2_250, 750] {
+            now += elapsed;
+            claim(&mut host, ALICE, now).expect("claiming");
+            // 1000 staked at 100% a year accrue 1000 * elapsed * 10000 per
+            // denominator; sub-second time counts too.
+            expected += 1_000 * u128::from(elapsed) * 10_000 % denominator;
+        }
+
+        assert_eq!(host.state().rounding_loss, expected);
+        let response = view_rounding_loss(&receive_ctx(ALICE, now, &[]), &host).expect("viewing the loss");
+        assert_eq!(response.remainder, expected);
+    }
+
+    fn batch_stake_at(
+        host: &mut Host,
+        funder: AccountAddress,
+        entries: &[(AccountAddress, u64)],
+    ) -> ContractResult<()> {
+        let entries: Vec<StakeForParams> = entries
+            .iter()
+            .map(|&(owner, amount)| StakeForParams {
+                owner,
+                amount,
+                token_contract_address: TOKEN,
+            })
+            .collect();
+        let parameter = to_bytes(&entries);
+        transact(host, |host| {
+            batch_stake(&receive_ctx(funder, START, &parameter), host, &mut TestLogger::init())
+        })
+    }
+
+    #[test]
+    fn batch_naming_an_owner_twice_is_rejected() {
+        let (mut host, transfers) = setup(init_params());
+
+        let result = batch_stake_at(&mut host, ADMIN, &[(ALICE, 1_000), (BOB, 1_000), (ALICE, 500)]);
+
+        assert_eq!(result, Err(CustomContractError::DuplicateOwnerInBatch.into()));
+        assert!(host.state().stake.get(&ALICE).is_none());
+        assert_eq!(host.state().total_staked, 0);
+        assert!(transfers.borrow().is_empty());
+    }
+
+    #[test]
+    fn batch_stakes_every_entry_from_the_funder() {
+        let (mut host, transfers) = setup(init_params());
+
+        batch_stake_at(&mut host, ADMIN, &[(ALICE, 1_000), (BOB, 500)]).expect("batch staking");
+
+        let position =
+            |owner| host.state().stake.get(&owner).map(|owner_state| (owner_state.amount, owner_state.funder));
+        assert_eq!(position(ALICE), Some((1_000, Some(ADMIN))));
+        assert_eq!(position(BOB), Some((500, Some(ADMIN))));
+        assert_eq!(host.state().total_staked, 1_500);
+        assert_eq!(host.state().staker_count, 2);
+        let funded: Vec<u64> = transfers
+            .borrow()
+            .iter()
+            .filter(|(from, _, _)| *from == Address::Account(ADMIN))
+            .map(|(_, _, amount)| *amount)
+            .collect();
+        assert_eq!(funded, vec![1_000, 500]);
+    }
+
+    #[test]
+    fn batch_with_a_failing_entry_reverts_entirely() {
+        let (mut host, _) = setup(init_params());
+        // The funder's balance covers the first entry but not the second.
+        mock_balance(&mut host, 800);
+
+        let result = batch_stake_at(&mut host, ADMIN, &[(ALICE, 500), (BOB, 1_000)]);
+
+        assert_eq!(result, Err(CustomContractError::NoBalance.into()));
+        assert!(host.state().stake.get(&ALICE).is_none());
+        assert_eq!(host.state().total_staked, 0);
+        assert_eq!(host.state().held_principal, 0);
+        assert!(!host.state().locked);
+    }
+
+    #[test]
+    fn amounts_are_in_the_smallest_unit_of_the_configured_decimals() {
+        let (mut host, _) = setup(InitParams {
+            token_decimals: 6,
+            ..init_params()
+        });
+        // One whole token at six decimals.
+        stake(&mut host, ALICE, 1_000_000, START).expect("staking");
+
+        let response = format_amounts(&receive_ctx(ALICE, START, &[]), &host).expect("formatting amounts");
+        assert_eq!(response.decimals, 6);
+        // A year at 100% earns one whole token, down to the last unit.
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 1_000_000);
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS / 1_000), 1_000);
+    }
+
+    #[test]
+    fn unstake_is_rejected_while_a_claim_is_in_flight() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        host.state_mut().begin_operation(&ALICE, PositionStatus::Claiming).expect("marking the claim");
+        host.commit_state();
+
+        let result = unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS);
+
+        assert_eq!(result.err(), Some(CustomContractError::PositionBusy.into()));
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+        assert!(paid_to(&transfers, ALICE).is_empty());
+    }
+
+    #[test]
+    fn unstake_request_is_rejected_while_a_claim_is_in_flight() {
+        let (mut host, _) = setup_cooldown();
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        host.state_mut().begin_operation(&ALICE, PositionStatus::Claiming).expect("marking the claim");
+        host.commit_state();
+
+        let result = request_unstake_at(&mut host, ALICE, 1_000, START + YEAR_MILLIS);
+
+        assert_eq!(result, Err(CustomContractError::PositionBusy.into()));
+        let position =
+            host.state().stake.get(&ALICE).map(|owner_state| (owner_state.amount, owner_state.unbonding_amount));
+        assert_eq!(position, Some((1_000, 0)));
+    }
+
+    #[test]
+    fn event_level_off_logs_nothing() {
+        let (mut host, _) = setup(InitParams {
+            event_level: EventLevel::Off,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+
+        let staked = stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let claimed = claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+
+        assert!(staked.logs.is_empty());
+        assert!(claimed.logs.is_empty());
+    }
+
+    #[test]
+    fn event_level_financial_logs_only_payouts() {
+        let (mut host, _) = setup(InitParams {
+            event_level: EventLevel::Financial,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+
+        let staked = stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let claimed = claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+
+        assert!(staked.logs.is_empty());
+        assert_eq!(claimed.logs, vec![logged(&StakingEvent::Claimed {
+            owner:  ALICE,
+            reward: 1_000,
+        })]);
+    }
+
+    #[test]
+    fn custody_balances_match_after_a_stake_and_a_funding() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        fund(&mut host, 500);
+        mock_balance(&mut host, 1_500);
+
+        let parameter = to_bytes(&TOKEN);
+        let response =
+            check_custody_balances(&receive_ctx(ADMIN, START, &parameter), &host).expect("checking the balances");
+
+        assert_eq!((response.held_principal, response.reward_pool, response.balance), (1_000, 500, 1_500));
+        assert!(response.in_sync);
+    }
+
+    #[test]
+    fn custody_balances_report_drift() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        fund(&mut host, 500);
+        // Ten tokens left the contract without being accounted for.
+        mock_balance(&mut host, 1_490);
+
+        let parameter = to_bytes(&TOKEN);
+        let response =
+            check_custody_balances(&receive_ctx(ADMIN, START, &parameter), &host).expect("checking the balances");
+
+        assert_eq!(response.balance, 1_490);
+        assert!(!response.in_sync);
+    }
+
+    const LOCK_MILLIS: u64 = 30 * 24 * 60 * 60 * 1000;
+
+    fn unlock_time(host: &Host, owner: AccountAddress, now: u64) -> u64 {
+        let parameter = to_bytes(&owner);
+        effective_unlock_time(&receive_ctx(owner, now, &parameter), host).expect("viewing the unlock time")
+    }
+
+    #[test]
+    fn unlock_time_under_a_lock_alone_is_the_lock_expiry() {
+        let (mut host, _) = setup(InitParams {
+            min_lock_seconds: LOCK_MILLIS / 1000,
+            ..init_params()
+        });
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        assert_eq!(unlock_time(&host, ALICE, START), START + LOCK_MILLIS);
+        assert_eq!(unlock_time(&host, ALICE, START + LOCK_MILLIS), 0);
+        assert_eq!(unlock_time(&host, BOB, START), 0);
+    }
+
+    #[test]
+    fn unlock_time_under_a_cooldown_alone_counts_from_the_request() {
+        let (mut host, _) = setup_cooldown();
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        // Not yet requested, so a request now would end the cooldown.
+        assert_eq!(unlock_time(&host, ALICE, START + YEAR_MILLIS), START + YEAR_MILLIS + COOLDOWN_MILLIS);
+
+        request_unstake_at(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("requesting the unstake");
+        assert_eq!(unlock_time(&host, ALICE, START + YEAR_MILLIS + 1_000), START + YEAR_MILLIS + COOLDOWN_MILLIS);
+        assert_eq!(unlock_time(&host, ALICE, START + YEAR_MILLIS + COOLDOWN_MILLIS), 0);
+    }
+
+    #[test]
+    fn unlock_time_under_a_lock_and_a_cooldown_adds_the_cooldown_to_the_lock() {
+        let (mut host, _) = setup(InitParams {
+            min_lock_seconds: LOCK_MILLIS / 1000,
+            cooldown_seconds: COOLDOWN_MILLIS / 1000,
+            ..init_params()
+        });
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        assert_eq!(unlock_time(&host, ALICE, START), START + LOCK_MILLIS + COOLDOWN_MILLIS);
+
+        request_unstake_at(&mut host, ALICE, 1_000, START + LOCK_MILLIS).expect("requesting the unstake");
+        assert_eq!(unlock_time(&host, ALICE, START + LOCK_MILLIS), START + LOCK_MILLIS + COOLDOWN_MILLIS);
+        assert_eq!(unlock_time(&host, ALICE, START + LOCK_MILLIS + COOLDOWN_MILLIS), 0);
+    }
+
+    #[test]
+    fn set_min_lock_keeps_the_lock_of_existing_positions() {
+        let (mut host, _) = setup(InitParams {
+            min_lock_seconds: LOCK_MILLIS / 1000,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let parameter = to_bytes(&(2 * LOCK_MILLIS / 1000));
+        transact(&mut host, |host| set_min_lock(&receive_ctx(ADMIN, START, &parameter), host))
+            .expect("setting the minimum lock");
+        stake(&mut host, BOB, 1_000, START).expect("staking");
+
+        assert_eq!(unlock_time(&host, ALICE, START), START + LOCK_MILLIS);
+        assert_eq!(unlock_time(&host, BOB, START), START + 2 * LOCK_MILLIS);
+        unstake(&mut host, ALICE, 1_000, START + LOCK_MILLIS).expect("unstaking under the old lock");
+        let result = unstake(&mut host, BOB, 1_000, START + LOCK_MILLIS);
+        assert_eq!(result.err(), Some(CustomContractError::StillLocked.into()));
+    }
+
+    /// Makes the token contract deny operator status, counting its queries.
+    fn deny_operator(host: &mut Host) -> Rc<RefCell<u32>> {
+        let queries = Rc::new(RefCell::new(0));
+        let counted = queries.clone();
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(OPERATOR_OF_ENTRYPOINT_NAME.into()),
+            MockFn::new_v1(move |_, _, _, _| {
+                *counted.borrow_mut() += 1;
+                Ok((false, OperatorOfQueryResponse::from(vec![false])))
+            }),
+        );
+        queries
+    }
+
+    #[test]
+    fn custody_stake_does_not_query_operator_status() {
+        let (mut host, _) = setup(init_params());
+        let queries = deny_operator(&mut host);
+
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        assert_eq!(*queries.borrow(), 0);
+        assert_eq!(host.state().total_staked, 1_000);
+    }
+
+    #[test]
+    fn operator_stake_requires_operator_status() {
+        let (mut host, _) = setup(InitParams {
+            custody: false,
+            ..init_params()
+        });
+        let queries = deny_operator(&mut host);
+
+        let result = stake(&mut host, ALICE, 1_000, START);
+
+        assert_eq!(result.err(), Some(CustomContractError::NotOperator.into()));
+        assert_eq!(*queries.borrow(), 1);
+        assert_eq!(host.state().total_staked, 0);
+    }
+
+    fn offer_position_at(host: &mut Host, from: AccountAddress, to: AccountAddress, expires_at: u64) {
+        let parameter = to_bytes(&OfferPositionParams {
+            to,
+            expires_at,
+        });
+        transact(host, |host| offer_position(&receive_ctx(from, START, &parameter), host)).expect("offering");
+    }
+
+    fn accept_position_at(
+        host: &mut Host,
+        sender: AccountAddress,
+        from: AccountAddress,
+        now: u64,
+    ) -> ContractResult<()> {
+        let parameter = to_bytes(&from);
+        transact(host, |host| accept_position(&receive_ctx(sender, now, &parameter), host))
+    }
+
+    #[test]
+    fn accepted_offer_moves_the_position_to_the_recipient() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        offer_position_at(&mut host, ALICE, BOB, START + YEAR_MILLIS);
+
+        accept_position_at(&mut host, BOB, ALICE, START + YEAR_MILLIS / 2).expect("accepting");
+
+        assert!(host.state().stake.get(&ALICE).is_none());
+        assert!(host.state().position_offers.get(&ALICE).is_none());
+        assert_eq!(host.state().stake.get(&BOB).map(|owner_state| owner_state.amount), Some(1_000));
+        // The position keeps accruing, now for Bob.
+        assert_eq!(pending_reward(&host, BOB, START + YEAR_MILLIS), 1_000);
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 0);
+    }
+
+    #[test]
+    fn expired_offer_cannot_be_accepted() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        offer_position_at(&mut host, ALICE, BOB, START + YEAR_MILLIS);
+
+        let result = accept_position_at(&mut host, BOB, ALICE, START + YEAR_MILLIS);
+
+        assert_eq!(result, Err(CustomContractError::OfferExpired.into()));
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+        assert!(host.state().stake.get(&BOB).is_none());
+    }
+
+    #[test]
+    fn offer_cannot_be_accepted_by_another_account() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        offer_position_at(&mut host, ALICE, BOB, START + YEAR_MILLIS);
+
+        let result = accept_position_at(&mut host, TREASURY, ALICE, START + 1_000);
+
+        assert_eq!(result, Err(ContractError::Unauthorized));
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+        assert!(host.state().stake.get(&TREASURY).is_none());
+        assert!(host.state().position_offers.get(&ALICE).is_some());
+    }
+
+    fn set_pause_flags_at(host: &mut Host, sender: AccountAddress, flags: u8) -> ContractResult<()> {
+        let parameter = to_bytes(&flags);
+        transact(host, |host| set_pause_flags(&receive_ctx(sender, START, &parameter), host))
+    }
+
+    /// Whether staking, claiming and unstaking are paused, in that order, as
+    /// seen by calling each of them.
+    fn paused_entrypoints(host: &mut Host) -> [bool; 3] {
+        let paused = |result: ContractResult<TestLogger>| {
+            result.err() == Some(CustomContractError::ContractPaused.into())
+        };
+        let now = START + YEAR_MILLIS;
+        [
+            paused(stake(host, BOB, 1_000, now)),
+            paused(claim(host, ALICE, now)),
+            paused(unstake(host, ALICE, 500, now)),
+        ]
+    }
+
+    #[test]
+    fn each_pause_flag_blocks_only_its_own_entrypoint() {
+        for (flags, expected) in [
+            (PAUSE_STAKE, [true, false, false]),
+            (PAUSE_CLAIM, [false, true, false]),
+            (PAUSE_UNSTAKE, [false, false, true]),
+            (0, [false, false, false]),
+        ] {
+            let (mut host, _) = setup(init_params());
+            fund(&mut host, 10_000);
+            stake(&mut host, ALICE, 1_000, START).expect("staking");
+            set_pause_flags_at(&mut host, ADMIN, flags).expect("setting the pause flags");
+
+            assert_eq!(paused_entrypoints(&mut host), expected, "pause flags {flags}");
+        }
+    }
+
+    fn set_paused_at(host: &mut Host, sender: AccountAddress, paused: bool) -> ContractResult<()> {
+        let parameter = to_bytes(&paused);
+        transact(host, |host| set_paused(&receive_ctx(sender, START, &parameter), host))
+    }
+
+    #[test]
+    fn set_paused_blocks_staking_and_claiming_but_not_unstaking() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        set_paused_at(&mut host, ADMIN, true).expect("pausing");
+        assert_eq!(paused_entrypoints(&mut host), [true, true, false]);
+
+        set_paused_at(&mut host, ADMIN, false).expect("unpausing");
+        assert_eq!(paused_entrypoints(&mut host), [false, false, false]);
+    }
+
+    #[test]
+    fn pausing_is_restricted_to_the_admin() {
+        let (mut host, _) = setup(init_params());
+
+        assert_eq!(set_paused_at(&mut host, ALICE, true), Err(ContractError::Unauthorized));
+        assert_eq!(set_pause_flags_at(&mut host, ALICE, PAUSE_UNSTAKE), Err(ContractError::Unauthorized));
+        assert_eq!(host.state().pause_flags, 0);
+    }
+
+    #[test]
+    fn calls_from_the_contract_itself_are_rejected() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let parameter = to_bytes(&ClaimParams {
+            owner:                  ALICE,
+            token_contract_address: TOKEN,
+        });
+        let mut ctx = receive_ctx(ALICE, START + YEAR_MILLIS, &parameter);
+        ctx.set_sender(Address::Contract(SELF_ADDRESS));
+        let result = transact(&mut host, |host| claim_reward(&ctx, host, &mut TestLogger::init()));
+
+        assert_eq!(result, Err(CustomContractError::SelfInvocation.into()));
+        assert!(!host.state().locked);
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 1_000);
+    }
+
+    #[test]
+    fn balance_response_of_the_wrong_length_is_rejected() {
+        // With two entries, the first one may answer a different query, e.g.
+        // for another token, so neither is trusted.
+        for balances in [vec![], vec![TokenAmountU64(0), TokenAmountU64(1_000)]] {
+            let (mut host, _) = setup(init_params());
+            host.setup_mock_entrypoint(
+                TOKEN,
+                OwnedEntrypointName::new_unchecked(BALANCE_OF_ENTRYPOINT_NAME.into()),
+                MockFn::new_v1(move |_, _, _, _| {
+                    Ok((false, ContractBalanceOfQueryResponse::from(balances.clone())))
+                }),
+            );
+
+            let result = stake(&mut host, ALICE, 1_000, START);
+
+            assert!(matches!(
+                result,
+                Err(Cis2Error::Custom(CustomContractError::Cis2ClientError(Cis2ClientError::InvalidResponse)))
+            ));
+            assert_eq!(host.state().total_staked, 0);
+        }
+    }
+
+    #[test]
+    fn stake_for_returns_the_principal_to_the_funder_and_the_reward_to_the_owner() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        let parameter = to_bytes(&StakeForParams {
+            owner:                  ALICE,
+            amount:                 1_000,
+            token_contract_address: TOKEN,
+        });
+        let mut logger = TestLogger::init();
+        transact(&mut host, |host| stake_for(&receive_ctx(BOB, START, &parameter), host, &mut logger))
+            .expect("staking for Alice");
+        assert_eq!(logger.logs, vec![logged(&StakingEvent::Staked {
+            owner:  ALICE,
+            amount: 1_000,
+        })]);
+        // Bob paid, Alice owns the position.
+        let funded = transfers.borrow().iter().filter(|(from, _, _)| *from == Address::Account(BOB)).count();
+        assert_eq!(funded, 1);
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 1_000);
+        assert_eq!(pending_reward(&host, BOB, START + YEAR_MILLIS), 0);
+
+        unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+        assert_eq!(paid_to(&transfers, BOB), vec![1_000]);
+    }
+
+    fn stake_lot(host: &mut Host, amount: u64, lock_seconds: u64) -> u64 {
+        let parameter = to_bytes(&StakeNewLotParams {
+            owner: ALICE,
+            amount,
+            lock_seconds,
+            token_contract_address: TOKEN,
+        });
+        transact(host, |host| stake_new_lot(&receive_ctx(ALICE, START, &parameter), host, &mut TestLogger::init()))
+            .expect("staking a lot")
+    }
+
+    fn unstake_lot_at(host: &mut Host, lot_id: u64, now: u64) -> ContractResult<()> {
+        let parameter = to_bytes(&UnstakeLotParams {
+            owner: ALICE,
+            lot_id,
+            token_contract_address: TOKEN,
+        });
+        transact(host, |host| unstake_lot(&receive_ctx(ALICE, now, &parameter), host, &mut TestLogger::init()))
+    }
+
+    #[test]
+    fn lots_with_different_locks_unlock_independently() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        let short = stake_lot(&mut host, 1_000, LOCK_MILLIS / 1000);
+        let long = stake_lot(&mut host, 2_000, 2 * LOCK_MILLIS / 1000);
+
+        let now = START + LOCK_MILLIS;
+        assert_eq!(unstake_lot_at(&mut host, long, now), Err(CustomContractError::StillLocked.into()));
+        unstake_lot_at(&mut host, short, now).expect("unstaking the short lot");
+        let remaining = host.state().lots.get(&ALICE).map(|lots| lots.iter().map(|(lot_id, _)| *lot_id).collect());
+        assert_eq!(remaining, Some(vec![long]));
+
+        unstake_lot_at(&mut host, long, START + 2 * LOCK_MILLIS).expect("unstaking the long lot");
+
+        // Each lot pays its own reward and returns its own principal.
+        let reward = |amount: u64, millis: u64| amount * millis / YEAR_MILLIS;
+        assert_eq!(paid_to(&transfers, ALICE), vec![
+            reward(1_000, LOCK_MILLIS),
+            1_000,
+            reward(2_000, 2 * LOCK_MILLIS),
+            2_000
+        ]);
+        assert_eq!(host.state().total_staked, 0);
+    }
+
+    const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+    fn claim_vested_at(host: &mut Host, now: u64) -> ContractResult<TestLogger> {
+        let parameter = to_bytes(&ClaimParams {
+            owner:                  ALICE,
+            token_contract_address: TOKEN,
+        });
+        let mut logger = TestLogger::init();
+        transact(host, |host| claim_vested(&receive_ctx(ALICE, now, &parameter), host, &mut logger))?;
+        Ok(logger)
+    }
+
+    #[test]
+    fn claimed_reward_vests_after_the_cliff_and_linearly_until_the_end() {
+        let (mut host, transfers) = setup(InitParams {
+            vesting: Some(VestingConfig {
+                cliff_seconds:    30 * DAY_MILLIS / 1000,
+                duration_seconds: 120 * DAY_MILLIS / 1000,
+            }),
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let claimed_at = START + YEAR_MILLIS;
+        claim(&mut host, ALICE, claimed_at).expect("claiming");
+        assert!(paid_to(&transfers, ALICE).is_empty());
+
+        let result = claim_vested_at(&mut host, claimed_at + 29 * DAY_MILLIS);
+        assert_eq!(result.err(), Some(CustomContractError::NothingVested.into()));
+
+        let logger = claim_vested_at(&mut host, claimed_at + 60 * DAY_MILLIS).expect("claiming half");
+        assert_eq!(paid_to(&transfers, ALICE), vec![500]);
+        assert_eq!(logger.logs, vec![logged(&StakingEvent::Claimed {
+            owner:  ALICE,
+            reward: 500,
+        })]);
+
+        claim_vested_at(&mut host, claimed_at + 200 * DAY_MILLIS).expect("claiming the rest");
+        assert_eq!(paid_to(&transfers, ALICE), vec![500, 500]);
+        assert!(host.state().vesting_entries.get(&ALICE).is_none());
+    }
+
+    fn claim_installment_at(host: &mut Host, now: u64) -> ContractResult<TestLogger> {
+        let parameter = to_bytes(&ClaimParams {
+            owner:                  ALICE,
+            token_contract_address: TOKEN,
+        });
+        let mut logger = TestLogger::init();
+        transact(host, |host| claim_installment(&receive_ctx(ALICE, now, &parameter), host, &mut logger))?;
+        Ok(logger)
+    }
+
+    #[test]
+    fn unstake_reward_is_released_in_installments_as_they_fall_due() {
+        let (mut host, transfers) = setup(InitParams {
+            installments: Some(InstallmentConfig {
+                count:            3,
+                interval_seconds: 10 * DAY_MILLIS / 1000,
+            }),
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let unstaked_at = START + YEAR_MILLIS;
+        unstake(&mut host, ALICE, 1_000, unstaked_at).expect("unstaking");
+        // Only the principal is returned right away.
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+
+        let result = claim_installment_at(&mut host, unstaked_at + 5 * DAY_MILLIS);
+        assert_eq!(result.err(), Some(CustomContractError::NoInstallmentDue.into()));
+
+        let logger = claim_installment_at(&mut host, unstaked_at + 10 * DAY_MILLIS).expect("claiming the first");
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000, 333]);
+        assert_eq!(logger.logs, vec![logged(&StakingEvent::Claimed {
+            owner:  ALICE,
+            reward: 333,
+        })]);
+
+        // The last installment takes the remainder of the split.
+        claim_installment_at(&mut host, unstaked_at + 30 * DAY_MILLIS).expect("claiming the rest");
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000, 333, 667]);
+        assert!(host.state().pending_installments.get(&ALICE).is_none());
+    }
+
+    #[test]
+    fn contract_token_balance_reports_the_token_contract_balance() {
+        let (mut host, _) = setup(init_params());
+        // Only the contract's own address holds anything.
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(BALANCE_OF_ENTRYPOINT_NAME.into()),
+            MockFn::new_v1(|parameter, _, _, _| {
+                let query: ContractBalanceOfQueryParams =
+                    from_bytes(parameter.as_ref()).map_err(|_| CallContractError::Trap)?;
+                let balances = query
+                    .queries
+                    .iter()
+                    .map(|query| query.address == Address::Contract(SELF_ADDRESS))
+                    .map(|is_self| TokenAmountU64(if is_self { 1_234 } else { 0 }))
+                    .collect::<Vec<_>>();
+                Ok((false, ContractBalanceOfQueryResponse::from(balances)))
+            }),
+        );
+
+        let parameter = to_bytes(&TOKEN);
+        let balance = contract_token_balance(&receive_ctx(ADMIN, START, &parameter), &host).expect("querying");
+
+        assert_eq!(balance, 1_234);
+    }
+
+    fn stake_with_key(host: &mut Host, amount: u64, idempotency_key: u64) -> ContractResult<()> {
+        let parameter = to_bytes(&StakeParams {
+            owner: ALICE,
+            amount,
+            token_contract_address: TOKEN,
+            idempotency_key: Some(idempotency_key),
+            referrer: None,
+        });
+        transact(host, |host| stake_token(&receive_ctx(ALICE, START, &parameter), host, &mut TestLogger::init()))
+    }
+
+    #[test]
+    fn replayed_idempotency_key_stakes_only_once() {
+        let (mut host, transfers) = setup(init_params());
+
+        stake_with_key(&mut host, 1_000, 7).expect("staking");
+        stake_with_key(&mut host, 1_000, 7).expect("replaying the stake");
+
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+        assert_eq!(host.state().total_staked, 1_000);
+        assert_eq!(transfers.borrow().len(), 1);
+
+        stake_with_key(&mut host, 1_000, 8).expect("staking with a new key");
+        assert_eq!(host.state().total_staked, 2_000);
+        assert_eq!(transfers.borrow().len(), 2);
+    }
+
+    #[test]
+    fn accrual_stops_at_the_season_end_and_restarts_with_a_new_season() {
+        let (mut host, _) = setup(InitParams {
+            season: Some(Season {
+                start: START,
+                end:   START + YEAR_MILLIS / 2,
+            }),
+            ..init_params()
+        });
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 500);
+
+        let parameter = to_bytes(&StartNewSeasonParams {
+            season:     Season {
+                start: START + YEAR_MILLIS,
+                end:   START + 2 * YEAR_MILLIS,
+            },
+            carry_over: true,
+        });
+        transact(&mut host, |host| start_new_season(&receive_ctx(ADMIN, START + YEAR_MILLIS, &parameter), host))
+            .expect("starting a new season");
+
+        // The carried over position keeps the first season's reward and
+        // accrues again through the new one.
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 500);
+        assert_eq!(pending_reward(&host, ALICE, START + 3 * YEAR_MILLIS / 2), 1_000);
+        assert_eq!(pending_reward(&host, ALICE, START + 3 * YEAR_MILLIS), 1_500);
+    }
+
+    #[test]
+    fn runway_is_the_pool_over_the_aggregate_accrual_rate() {
+        let (mut host, _) = setup(InitParams {
+            reward_rate_per_year_bps: 2_500,
+            ..init_params()
+        });
+        fund(&mut host, 250);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        stake(&mut host, BOB, 3_000, START).expect("staking");
+
+        // 4000 staked at 25% a year drain 1000 a year, so 250 last a quarter
+        // of a year.
+        let runway = view_reward_pool_runway(&receive_ctx(ADMIN, START, &[]), &host).expect("viewing the runway");
+        assert_eq!(runway, 7_884_000);
+    }
+
+    #[test]
+    fn removing_a_staker_updates_every_aggregate() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        stake(&mut host, BOB, 500, START).expect("staking");
+
+        host.state_mut().remove_staker(&ALICE).expect("removing the staker");
+
+        let state = host.state();
+        assert!(state.stake.get(&ALICE).is_none());
+        assert_eq!(state.total_staked, 500);
+        assert_eq!(state.staker_count, 1);
+        assert_eq!(state.stake.iter().count(), 1);
+    }
+
+    fn stake_referred(host: &mut Host, owner: AccountAddress, referrer: AccountAddress) -> ContractResult<()> {
+        let parameter = to_bytes(&StakeParams {
+            owner,
+            amount: 1_000,
+            token_contract_address: TOKEN,
+            idempotency_key: None,
+            referrer: Some(referrer),
+        });
+        transact(host, |host| stake_token(&receive_ctx(owner, START, &parameter), host, &mut TestLogger::init()))
+    }
+
+    #[test]
+    fn referrer_claims_the_bonus_of_a_referred_stake() {
+        let (mut host, transfers) = setup(InitParams {
+            referral_bonus_bps: 1_000,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake_referred(&mut host, BOB, ALICE).expect("staking");
+
+        let parameter = to_bytes(&TOKEN);
+        let mut logger = TestLogger::init();
+        transact(&mut host, |host| {
+            claim_referral_reward(&receive_ctx(ALICE, START + 1_000, &parameter), host, &mut logger)
+        })
+        .expect("claiming the referral reward");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![100]);
+        assert_eq!(logger.logs, vec![logged(&StakingEvent::Claimed {
+            owner:  ALICE,
+            reward: 100,
+        })]);
+        assert!(host.state().referral_rewards.get(&ALICE).is_none());
+        assert_eq!(host.state().reward_pool, 9_900);
+    }
+
+    #[test]
+    fn self_referral_is_rejected() {
+        let (mut host, _) = setup(InitParams {
+            referral_bonus_bps: 1_000,
+            ..init_params()
+        });
+
+        let result = stake_referred(&mut host, ALICE, ALICE);
+
+        assert_eq!(result, Err(CustomContractError::SelfReferral.into()));
+        assert_eq!(host.state().total_staked, 0);
+    }
+
+    #[test]
+    fn deserial_versioned_decodes_unversioned_events() {
+        let event = StakingEvent::Staked {
+            owner:  ALICE,
+            amount: 1_000,
+        };
+        let v0 = to_bytes(&event);
+
+        let decoded = StakingEvent::deserial_versioned(&mut Cursor::new(&v0)).expect("decoding v0");
+
+        assert_eq!(to_bytes(&decoded), v0);
+    }
+
+    #[test]
+    fn deserial_versioned_round_trips_logged_events() {
+        let event = StakingEvent::Claimed {
+            owner:  ALICE,
+            reward: 500,
+        };
+        let v1 = logged(&event);
+
+        let decoded = StakingEvent::deserial_versioned(&mut Cursor::new(&v1)).expect("decoding v1");
+
+        assert_eq!(logged(&decoded), v1);
+        let mut future = v1;
+        future[0] = EVENT_VERSION_MARKER | (EVENT_FORMAT_VERSION + 1);
+        assert!(StakingEvent::deserial_versioned(&mut Cursor::new(&future)).is_err());
+    }
+
+    #[test]
+    fn stake_before_program_start_accrues_from_program_start() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000_000, START - 10 * DAY_MILLIS).expect("staking");
+        stake(&mut host, BOB, 1_000_000, START).expect("staking");
+
+        let now = START + 5 * DAY_MILLIS;
+        let five_days = 1_000_000 * 5 * DAY_MILLIS / YEAR_MILLIS;
+        assert_eq!(pending_reward(&host, ALICE, now), five_days);
+        assert_eq!(pending_reward(&host, BOB, now), five_days);
+    }
+
+    fn setup_ccd(params: InitParams) -> (Host, Transfers) {
+        let (mut host, transfers) = setup(InitParams {
+            tokens_per_ccd: Some(1_000_000),
+            ..params
+        });
+        fund(&mut host, 10_000);
+        (host, transfers)
+    }
+
+    fn stake_ccd_at(host: &mut Host, micro_ccd: u64, now: u64) -> ContractResult<()> {
+        let amount = Amount::from_micro_ccd(micro_ccd);
+        host.set_self_balance(host.self_balance() + amount);
+        transact(host, |host| stake_ccd(&receive_ctx(ALICE, now, &[]), host, amount, &mut TestLogger::init()))
+    }
+
+    fn unstake_ccd_at(host: &mut Host, now: u64) -> ContractResult<()> {
+        let parameter = to_bytes(&TOKEN);
+        transact(host, |host| unstake_ccd(&receive_ctx(ALICE, now, &parameter), host, &mut TestLogger::init()))
+    }
+
+    fn withdraw_ccd_at(host: &mut Host, now: u64) -> ContractResult<()> {
+        transact(host, |host| withdraw_ccd(&receive_ctx(ALICE, now, &[]), host, &mut TestLogger::init()))
+    }
+
+    #[test]
+    fn unstake_ccd_returns_the_ccd_and_pays_the_token_reward() {
+        let (mut host, transfers) = setup_ccd(init_params());
+        stake_ccd_at(&mut host, 1_000, START).expect("staking CCD");
+        assert_eq!(host.state().total_ccd_staked, 1_000);
+
+        unstake_ccd_at(&mut host, START + YEAR_MILLIS).expect("unstaking CCD");
+
+        assert_eq!(host.get_transfers(), vec![(ALICE, Amount::from_micro_ccd(1_000))]);
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+        assert_eq!(host.state().total_ccd_staked, 0);
+        assert!(host.state().ccd_stake.get(&ALICE).is_none());
+    }
+
+    #[test]
+    fn unstake_ccd_honors_the_minimum_lock() {
+        let (mut host, _) = setup_ccd(InitParams {
+            min_lock_seconds: LOCK_MILLIS / 1000,
+            ..init_params()
+        });
+        stake_ccd_at(&mut host, 1_000, START).expect("staking CCD");
+
+        let result = unstake_ccd_at(&mut host, START + LOCK_MILLIS - 1);
+
+        assert_eq!(result, Err(CustomContractError::StillLocked.into()));
+        unstake_ccd_at(&mut host, START + LOCK_MILLIS).expect("unstaking CCD once unlocked");
+        assert_eq!(host.get_transfers(), vec![(ALICE, Amount::from_micro_ccd(1_000))]);
+    }
+
+    #[test]
+    fn unstake_ccd_with_a_cooldown_returns_the_ccd_through_withdraw_ccd() {
+        let (mut host, transfers) = setup_ccd(InitParams {
+            cooldown_seconds: COOLDOWN_MILLIS / 1000,
+            ..init_params()
+        });
+        stake_ccd_at(&mut host, 1_000, START).expect("staking CCD");
+        let unstaked_at = START + YEAR_MILLIS;
+
+        unstake_ccd_at(&mut host, unstaked_at).expect("unstaking CCD");
+
+        assert!(host.get_transfers().is_empty());
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+        assert_eq!(unstake_ccd_at(&mut host, unstaked_at), Err(CustomContractError::CoolingDown.into()));
+        let early = withdraw_ccd_at(&mut host, unstaked_at + COOLDOWN_MILLIS - 1);
+        assert_eq!(early, Err(CustomContractError::CoolingDown.into()));
+        withdraw_ccd_at(&mut host, unstaked_at + COOLDOWN_MILLIS).expect("withdrawing CCD");
+        assert_eq!(host.get_transfers(), vec![(ALICE, Amount::from_micro_ccd(1_000))]);
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+        assert!(host.state().ccd_stake.get(&ALICE).is_none());
+    }
+
+    fn apply_penalty_at(host: &mut Host, owner: AccountAddress, amount: u64) {
+        let parameter = to_bytes(&ApplyPenaltyParams {
+            owner,
+            amount,
+        });
+        transact(host, |host| apply_penalty(&receive_ctx(ADMIN, START, &parameter), host))
+            .expect("applying the penalty");
+    }
+
+    #[test]
+    fn penalty_partially_offsets_accrued_reward() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        apply_penalty_at(&mut host, ALICE, 400);
+
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 600);
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+        assert_eq!(paid_to(&transfers, ALICE), vec![600]);
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.adjustment), Some(0));
+    }
+
+    #[test]
+    fn penalty_fully_offsets_accrued_reward_without_touching_principal() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        apply_penalty_at(&mut host, ALICE, 1_500);
+
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 0);
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+        // What the first year's reward did not cover is netted against the next.
+        assert_eq!(pending_reward(&host, ALICE, START + 2 * YEAR_MILLIS), 500);
+    }
+
+    #[test]
+    fn stake_overflowing_total_staked_is_rejected() {
+        let (mut host, _) = setup(init_params());
+        host.state_mut().total_staked = u64::MAX - 10;
+        host.commit_state();
+
+        let result = stake(&mut host, ALICE, 1_000, START);
+
+        assert_eq!(result.err(), Some(CustomContractError::AmountOverflow.into()));
+        assert_eq!(host.state().total_staked, u64::MAX - 10);
+        assert!(host.state().stake.get(&ALICE).is_none());
+    }
+
+    #[test]
+    fn unstake_of_more_than_total_staked_is_rejected() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        host.state_mut().total_staked = 500;
+        host.commit_state();
+
+        let result = unstake(&mut host, ALICE, 1_000, START + 1_000);
+
+        assert_eq!(result.err(), Some(CustomContractError::AmountOverflow.into()));
+        assert_eq!(host.state().total_staked, 500);
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+    }
+
+    fn capabilities_of(host: &Host) -> Vec<Capability> {
+        let response = capabilities(&receive_ctx(ALICE, START, &[]), host).expect("viewing the capabilities");
+        assert_eq!(response.event_format_version, EVENT_FORMAT_VERSION);
+        response.capabilities
+    }
+
+    #[test]
+    fn capabilities_of_a_plain_custody_instance() {
+        let (host, _) = setup(init_params());
+
+        assert!(capabilities_of(&host) == vec![Capability::Custody, Capability::Lots]);
+    }
+
+    #[test]
+    fn capabilities_follow_the_configured_behaviors() {
+        let (host, _) = setup(InitParams {
+            custody: false,
+            tokens_per_ccd: Some(1_000_000),
+            referral_bonus_bps: 1_000,
+            ..init_params()
+        });
+
+        assert!(
+            capabilities_of(&host)
+                == vec![Capability::OperatorStaking, Capability::Lots, Capability::CcdStaking, Capability::Referrals]
+        );
+    }
+
+    #[test]
+    fn get_stake_info_returns_the_position_or_an_empty_one() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let parameter = to_bytes(&ALICE);
+        let info = get_stake_info(&receive_ctx(BOB, START, &parameter), &host).expect("viewing ALICE's stake");
+        assert_eq!(info.amount, 1_000);
+        assert_eq!(info.staked_start_at, START);
+        assert_eq!(info.last_claimed_at, START);
+
+        let parameter = to_bytes(&BOB);
+        let info = get_stake_info(&receive_ctx(BOB, START, &parameter), &host).expect("viewing BOB's stake");
+        assert_eq!(to_bytes(&info), to_bytes(&StakeState::empty()));
+    }
+
+    #[test]
+    fn view_total_staked_sums_every_account() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        stake(&mut host, BOB, 2_500, START).expect("staking");
+        let total_staked = |host: &Host| {
+            view_total_staked(&receive_ctx(ALICE, START, &[]), host).expect("viewing the total staked")
+        };
+
+        assert_eq!(total_staked(&host), 3_500);
+        unstake(&mut host, ALICE, 1_000, START + 1_000).expect("unstaking");
+        assert_eq!(total_staked(&host), 2_500);
+    }
+
+    #[test]
+    fn pool_rate_emission_is_split_in_proportion_to_stake() {
+        let (mut host, transfers) = setup(InitParams {
+            reward_per_second: Some(10),
+            ..init_params()
+        });
+        fund(&mut host, 100_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        stake(&mut host, BOB, 3_000, START).expect("staking");
+
+        let now = START + 1_000 * 1_000;
+        assert_eq!(pending_reward(&host, ALICE, now), 2_500);
+        assert_eq!(pending_reward(&host, BOB, now), 7_500);
+        claim(&mut host, ALICE, now).expect("claiming");
+        claim(&mut host, BOB, now).expect("claiming");
+        assert_eq!(paid_to(&transfers, ALICE), vec![2_500]);
+        assert_eq!(paid_to(&transfers, BOB), vec![7_500]);
+    }
+
+    fn max_withdrawable(host: &Host, owner: AccountAddress, now: u64) -> ContractResult<u64> {
+        let parameter = to_bytes(&owner);
+        view_max_withdrawable(&receive_ctx(owner, now, &parameter), host)
+    }
+
+    #[test]
+    fn max_withdrawable_is_the_position_after_the_lock_less_the_fee() {
+        let (mut host, _) = setup(InitParams {
+            min_lock_seconds: LOCK_MILLIS / 1000,
+            unstake_fee_bps: 100,
+            ..init_params()
+        });
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        assert_eq!(max_withdrawable(&host, ALICE, START + LOCK_MILLIS - 1), Ok(0));
+        assert_eq!(max_withdrawable(&host, ALICE, START + LOCK_MILLIS), Ok(990));
+        assert_eq!(max_withdrawable(&host, BOB, START + LOCK_MILLIS), Ok(0));
+    }
+
+    #[test]
+    fn max_withdrawable_is_rejected_outside_custody_mode() {
+        let (host, _) = setup(InitParams {
+            custody: false,
+            ..init_params()
+        });
+
+        assert_eq!(max_withdrawable(&host, ALICE, START), Err(CustomContractError::NotCustody.into()));
+    }
+
+    #[test]
+    fn claim_with_rewards_disabled_pays_nothing() {
+        let (mut host, transfers) = setup(InitParams {
+            rewards_enabled: false,
+            ..init_params()
+        });
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 0);
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+
+        assert!(paid_to(&transfers, ALICE).is_empty());
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+    }
+
+    #[test]
+    fn force_settle_all_settles_a_page_and_returns_the_next_cursor() {
+        let (mut host, _) = setup(init_params());
+        let count = MAX_SETTLE_BATCH as u16 + 1;
+        for i in 0..count {
+            stake(&mut host, staker(i), 1_000, START).expect("staking");
+        }
+        let now = START + YEAR_MILLIS;
+        let settle_from = |host: &mut Host, skip: u64| {
+            let parameter = to_bytes(&skip);
+            transact(host, |host| force_settle_all(&receive_ctx(ADMIN, now, &parameter), host))
+                .expect("settling")
+        };
+        let settled = |host: &Host| {
+            host.state().stake.iter().filter(|(_, owner_state)| owner_state.last_claimed_at == now).count()
+        };
+
+        assert_eq!(settle_from(&mut host, 0), Some(MAX_SETTLE_BATCH as u64));
+        assert_eq!(settled(&host), MAX_SETTLE_BATCH);
+        assert_eq!(host.state().total_liabilities, 1_000 * MAX_SETTLE_BATCH as u128);
+
+        assert_eq!(settle_from(&mut host, MAX_SETTLE_BATCH as u64), None);
+        assert_eq!(settled(&host), usize::from(count));
+        assert!(host.state().stake.iter().all(|(_, owner_state)| owner_state.accrued == 1_000));
+        assert_eq!(host.state().total_liabilities, 1_000 * u128::from(count));
+    }
+
+    /// Makes the token contract record the additional data of each transfer
+    /// instead of the transfers themselves.
+    fn record_transfer_data(host: &mut Host) -> Rc<RefCell<Vec<Vec<u8>>>> {
+        let data = Rc::new(RefCell::new(Vec::new()));
+        let recorded = data.clone();
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(TRANSFER_ENTRYPOINT_NAME.into()),
+            MockFn::new_v0::<(), _>(move |parameter, _, _, _| {
+                let params: TransferParameter =
+                    from_bytes(parameter.as_ref()).map_err(|_| CallContractError::Trap)?;
+                for transfer in params.0 {
+                    recorded.borrow_mut().push(transfer.data.as_ref().to_vec());
+                }
+                Ok(false)
+            }),
+        );
+        data
+    }
+
+    fn memo(kind: PayoutKind, account: AccountAddress) -> Vec<u8> {
+        let mut memo = vec![kind as u8];
+        memo.extend_from_slice(&account.0);
+        memo
+    }
+
+    #[test]
+    fn payout_memo_tags_each_payout_with_its_kind_and_account() {
+        let (mut host, _) = setup(InitParams {
+            payout_memo: true,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let data = record_transfer_data(&mut host);
+
+        unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking");
+
+        assert_eq!(*data.borrow(), vec![memo(PayoutKind::Reward, ALICE), memo(PayoutKind::Principal, ALICE)]);
+    }
+
+    #[test]
+    fn payouts_carry_no_data_without_payout_memo() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let data = record_transfer_data(&mut host);
+
+        unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking");
+
+        assert_eq!(*data.borrow(), vec![Vec::<u8>::new(), Vec::new()]);
+    }
+
+    #[test]
+    fn unstake_with_a_different_token_contract_is_rejected() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let parameter = to_bytes(&UnStakeParams {
+            owner:                  ALICE,
+            token_contract_address: ContractAddress {
+                index:    99,
+                subindex: 0,
+            },
+            amount:                 1_000,
+        });
+
+        let result = transact(&mut host, |host| {
+            unstake_token(&receive_ctx(ALICE, START + YEAR_MILLIS, &parameter), host, &mut TestLogger::init())
+        });
+
+        assert_eq!(result, Err(CustomContractError::WrongTokenContract.into()));
+        assert!(paid_to(&transfers, ALICE).is_empty());
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+    }
+
+    #[test]
+    fn stake_into_a_contract_without_cis2_support_is_rejected() {
+        let (mut host, transfers) = setup(init_params());
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(SUPPORTS_ENTRYPOINT_NAME.into()),
+            MockFn::new_v1(|_, _, _, _| {
+                Ok((false, SupportsQueryResponse {
+                    results: vec![SupportResult::NoSupport],
+                }))
+            }),
+        );
+
+        let result = stake(&mut host, ALICE, 1_000, START);
+
+        assert_eq!(result.err(), Some(CustomContractError::UnsupportedTokenContract.into()));
+        assert!(transfers.borrow().is_empty());
+        assert_eq!(host.state().total_staked, 0);
+    }
+
+    #[test]
+    fn list_stakers_pages_through_every_position() {
+        let (mut host, _) = setup(init_params());
+        for i in 0..5 {
+            stake(&mut host, staker(i), 1_000 + u64::from(i), START).expect("staking");
+        }
+        let page = |start: u64| {
+            let parameter = to_bytes(&ListStakersParams {
+                start,
+                limit: 2,
+            });
+            list_stakers(&receive_ctx(ALICE, START, &parameter), &host).expect("listing stakers")
+        };
+
+        let count = get_stakers_count(&receive_ctx(ALICE, START, &[]), &host).expect("counting stakers");
+        assert_eq!(count, 5);
+        let pages = [page(0), page(2), page(4), page(6)];
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 2, 1, 0]);
+        let mut listed: Vec<(AccountAddress, u64)> =
+            pages.iter().flatten().map(|(owner, owner_state)| (*owner, owner_state.amount)).collect();
+        listed.sort();
+        let mut expected: Vec<(AccountAddress, u64)> = (0..5).map(|i| (staker(i), 1_000 + u64::from(i))).collect();
+        expected.sort();
+        assert_eq!(listed, expected);
+    }
+
+    #[test]
+    fn emergency_withdraw_returns_the_principal_with_an_empty_reward_pool() {
+        let (mut host, transfers) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        assert_eq!(host.state().reward_pool, 0);
+        mock_balance(&mut host, 1_000);
+
+        let parameter = to_bytes(&TOKEN);
+        transact(&mut host, |host| {
+            emergency_withdraw(&receive_ctx(ALICE, START + YEAR_MILLIS, &parameter), host, &mut TestLogger::init())
+        })
+        .expect("withdrawing");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+        assert_eq!(host.state().total_staked, 0);
+        assert_eq!(host.state().liabilities_at(START + YEAR_MILLIS), 0);
+        assert!(host.state().stake.get(&ALICE).is_none());
+    }
+
+    #[test]
+    fn underfunded_reward_source_leaves_the_stake_intact() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        // The ledger says the pool is funded, but the token contract holds
+        // less than the reward.
+        mock_balance(&mut host, 500);
+
+        let claimed = claim(&mut host, ALICE, START + YEAR_MILLIS);
+        let unstaked = unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS);
+
+        assert_eq!(claimed.err(), Some(CustomContractError::NoBalance.into()));
+        assert_eq!(unstaked.err(), Some(CustomContractError::NoBalance.into()));
+        assert!(paid_to(&transfers, ALICE).is_empty());
+        assert_eq!(host.state().total_staked, 1_000);
+        assert_eq!(host.state().reward_pool, 10_000);
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 1_000);
+    }
+
+    #[test]
+    fn stake_up_to_the_cap_is_accepted_and_past_it_rejected() {
+        let (mut host, _) = setup(InitParams {
+            max_stake_per_account: Some(1_000),
+            ..init_params()
+        });
+
+        stake(&mut host, ALICE, 1_000, START).expect("staking exactly the cap");
+        let in_one_go = stake(&mut host, BOB, 1_001, START);
+        let across_deposits = stake(&mut host, ALICE, 1, START + 1_000);
+
+        assert_eq!(in_one_go.err(), Some(CustomContractError::StakeCapExceeded.into()));
+        assert_eq!(across_deposits.err(), Some(CustomContractError::StakeCapExceeded.into()));
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+        assert_eq!(host.state().total_staked, 1_000);
+    }
+
+    #[test]
+    fn first_deposit_must_reach_the_minimum_stake() {
+        let (mut host, _) = setup(InitParams {
+            min_stake_amount: 100,
+            ..init_params()
+        });
+
+        let below = stake(&mut host, ALICE, 99, START);
+        stake(&mut host, BOB, 100, START).expect("staking exactly the minimum");
+        // Only the deposit opening a position has to reach the minimum.
+        stake(&mut host, BOB, 1, START + 1_000).expect("topping up below the minimum");
+
+        assert_eq!(below.err(), Some(CustomContractError::BelowMinimumStake.into()));
+        assert!(host.state().stake.get(&ALICE).is_none());
+        assert_eq!(host.state().stake.get(&BOB).map(|owner_state| owner_state.amount), Some(101));
+    }
+
+    #[test]
+    fn view_timing_reports_the_stored_start_and_elapsed_seconds() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START + 250).expect("staking");
+        let now = START + 90_999;
+
+        let parameter = to_bytes(&ALICE);
+        let timing = view_timing(&receive_ctx(ALICE, now, &parameter), &host).expect("viewing the timing");
+
+        assert_eq!(timing.staked_start_at_millis, START + 250);
+        assert_eq!(timing.now_millis, now);
+        assert_eq!(timing.elapsed_seconds, (now - (START + 250)) / 1000);
+        assert_eq!(timing.elapsed_seconds, 90);
+    }
+
+    #[test]
+    fn restake_after_a_full_unstake_accrues_from_the_new_stake() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking");
+
+        let restaked_at = START + 2 * YEAR_MILLIS;
+        stake(&mut host, ALICE, 1_000, restaked_at).expect("staking again");
+
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.staked_start_at), Some(restaked_at));
+        assert_eq!(pending_reward(&host, ALICE, restaked_at), 0);
+        assert_eq!(pending_reward(&host, ALICE, restaked_at + YEAR_MILLIS), 1_000);
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000, 1_000]);
+    }
+
+    #[test]
+    fn top_up_settles_the_reward_accrued_before_it() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        stake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("topping up");
+
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.accrued), Some(1_000));
+        assert_eq!(pending_reward(&host, ALICE, START + 2 * YEAR_MILLIS), 3_000);
+        claim(&mut host, ALICE, START + 2 * YEAR_MILLIS).expect("claiming");
+        assert_eq!(paid_to(&transfers, ALICE), vec![3_000]);
+    }
+
+    #[test]
+    fn partial_unstake_settles_the_reward_accrued_before_it() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 2_000, START).expect("staking");
+
+        unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("unstaking half");
+
+        // The whole position's reward so far is paid with the principal.
+        assert_eq!(paid_to(&transfers, ALICE), vec![2_000, 1_000]);
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.accrued), Some(0));
+        assert_eq!(pending_reward(&host, ALICE, START + 2 * YEAR_MILLIS), 1_000);
+    }
+
+    #[test]
+    fn projected_maturity_value_adds_the_reward_over_the_horizon() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000_000, START).expect("staking");
+        let now = START + YEAR_MILLIS;
+
+        let parameter = to_bytes(&ALICE);
+        let projection =
+            projected_maturity_value(&receive_ctx(ALICE, now, &parameter), &host).expect("projecting the value");
+
+        let matures_at = now + DEFAULT_PROJECTION_HORIZON_MILLIS;
+        assert_eq!(projection.matures_at, matures_at);
+        assert_eq!(projection.value, 1_000_000 + 1_000_000 * (matures_at - START) / YEAR_MILLIS);
+    }
+
+    #[test]
+    fn reward_is_paid_from_the_contract_only_while_it_holds_the_reward() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+        assert!(matches!(
+            transfers.borrow().last(),
+            Some((Address::Contract(SELF_ADDRESS), Receiver::Account(ALICE), 1_000))
+        ));
+
+        // The contract no longer holds what its ledger says it owes.
+        mock_balance(&mut host, 999);
+        let result = claim(&mut host, ALICE, START + 2 * YEAR_MILLIS);
+        assert_eq!(result.err(), Some(CustomContractError::NoBalance.into()));
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+    }
+
+    #[test]
+    fn claim_in_a_no_accrual_window_pays_nothing_for_it_and_keeps_the_principal() {
+        let (mut host, transfers) = setup(InitParams {
+            no_accrual_windows: vec![(START + YEAR_MILLIS / 2, START + YEAR_MILLIS)],
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        claim(&mut host, ALICE, START + YEAR_MILLIS / 2).expect("claiming");
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming within the window");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![500]);
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+        assert_eq!(host.state().total_staked, 1_000);
+    }
+
+    #[test]
+    fn claim_while_claiming_is_paused_is_blocked_and_keeps_the_principal() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        set_pause_flags_at(&mut host, ADMIN, PAUSE_CLAIM).expect("pausing claims");
+
+        let result = claim(&mut host, ALICE, START + YEAR_MILLIS);
+
+        assert_eq!(result.err(), Some(CustomContractError::ContractPaused.into()));
+        assert!(paid_to(&transfers, ALICE).is_empty());
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+        // Accrual went on while paused.
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 1_000);
+    }
+
+    #[test]
+    fn reward_transfer_is_invoked_on_the_token_contract() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let recorded = calls.clone();
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(TRANSFER_ENTRYPOINT_NAME.into()),
+            MockFn::new_v0::<(), _>(move |parameter, _, _, _| {
+                let params: TransferParameter =
+                    from_bytes(parameter.as_ref()).map_err(|_| CallContractError::Trap)?;
+                recorded.borrow_mut().push(params.0.iter().map(|transfer| transfer.amount.0).collect::<Vec<_>>());
+                Ok(false)
+            }),
+        );
+
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+
+        // One `transfer` call carrying the reward.
+        assert_eq!(*calls.borrow(), vec![vec![1_000]]);
+    }
+
+    #[test]
+    fn unstake_returns_the_principal_along_with_the_reward() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        unstake(&mut host, ALICE, 1_000, START + YEAR_MILLIS / 2).expect("unstaking");
+
+        // The reward first, then the principal.
+        assert_eq!(paid_to(&transfers, ALICE), vec![500, 1_000]);
+        assert!(host.state().stake.get(&ALICE).is_none());
+    }
+
+    #[test]
+    fn claim_keeps_the_principal_and_pays_the_same_for_the_next_interval() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        claim(&mut host, ALICE, START + YEAR_MILLIS / 2).expect("claiming");
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+        assert_eq!(host.state().total_staked, 1_000);
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming again");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![500, 500]);
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_000));
+    }
+
+    #[test]
+    fn time_before_the_stake_start_counts_as_zero_seconds() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START + YEAR_MILLIS).expect("staking");
+
+        assert_eq!(host.state().elapsed_seconds(START + YEAR_MILLIS, START), 0);
+        assert_eq!(host.state().get_time(&ALICE, START), Ok(0));
+        assert_eq!(pending_reward(&host, ALICE, START), 0);
+    }
+
+    #[test]
+    fn repeated_stakes_add_up() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        stake(&mut host, ALICE, 500, START + 1_000).expect("staking again");
+
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.amount), Some(1_500));
+        assert_eq!(host.state().total_staked, 1_500);
+        assert_eq!(host.state().staker_count, 1);
+    }
+
+    #[test]
+    fn stake_and_unstake_in_one_block_leave_a_consistent_state() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 10_000);
+
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        unstake(&mut host, ALICE, 1_000, START).expect("unstaking in the same block");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000]);
+        let state = host.state();
+        assert!(state.stake.get(&ALICE).is_none());
+        assert_eq!(state.total_staked, 0);
+        assert_eq!(state.staker_count, 0);
+        assert_eq!(state.reward_pool, 10_000);
+        assert_eq!(state.liabilities_at(START), 0);
+        assert!(!state.locked);
+    }
+
+    #[test]
+    fn pending_reward_after_half_a_year_is_half_the_amount() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 10_000, START).expect("staking");
+
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS / 2), 5_000);
+        assert_eq!(pending_reward(&host, BOB, START + YEAR_MILLIS / 2), 0);
+    }
+
+    #[test]
+    fn stake_claim_and_unstake_log_their_events() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+
+        let staked = stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let claimed = claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+        let unstaked = unstake(&mut host, ALICE, 1_000, START + 2 * YEAR_MILLIS).expect("unstaking");
+
+        assert_eq!(staked.logs, vec![logged(&StakingEvent::Staked {
+            owner:  ALICE,
+            amount: 1_000,
+        })]);
+        assert_eq!(claimed.logs, vec![logged(&StakingEvent::Claimed {
+            owner:  ALICE,
+            reward: 1_000,
+        })]);
+        assert_eq!(unstaked.logs, vec![logged(&StakingEvent::Unstaked {
+            owner:  ALICE,
+            amount: 1_000,
+            reward: 1_000,
+        })]);
+    }
+
+    #[test]
+    fn five_percent_rate_pays_five_percent_a_year() {
+        let (mut host, _) = setup(InitParams {
+            reward_rate_per_year_bps: 500,
+            ..init_params()
+        });
+        stake(&mut host, ALICE, 10_000, START).expect("staking");
+
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 500);
+    }
+
+    #[test]
+    fn stake_age_buckets_group_positions_by_their_last_deposit() {
+        let (mut host, _) = setup(init_params());
+        let day = 24 * 60 * 60 * 1000;
+        let now = START + 100 * day;
+        let deposits = [(ALICE, now - 1_000, 100), (BOB, now - 2 * day, 200), (TREASURY, now - 10 * day, 300)];
+        for (owner, deposited_at, amount) in deposits {
+            stake(&mut host, owner, amount, deposited_at).expect("staking");
+        }
+        stake(&mut host, staker(0), 400, now - 60 * day).expect("staking");
+        stake(&mut host, staker(1), 500, START).expect("staking");
+
+        let parameter = to_bytes(&0u64);
+        let response = view_stake_age_buckets(&receive_ctx(ALICE, now, &parameter), &host).expect("bucketing");
+
+        let buckets: Vec<(u64, u64)> = response.buckets.iter().map(|bucket| (bucket.count, bucket.amount)).collect();
+        assert_eq!(buckets, vec![(1, 100), (1, 200), (1, 300), (2, 900)]);
+        assert_eq!(response.next, None);
+    }
+
+    #[test]
+    fn unstake_is_rejected_until_the_minimum_lock_has_passed() {
+        let (mut host, transfers) = setup(InitParams {
+            min_lock_seconds: LOCK_MILLIS / 1000,
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+
+        let early = unstake(&mut host, ALICE, 1_000, START + LOCK_MILLIS - 1);
+        assert_eq!(early.err(), Some(CustomContractError::StillLocked.into()));
+        assert_eq!(host.state().total_staked, 1_000);
+
+        unstake(&mut host, ALICE, 1_000, START + LOCK_MILLIS).expect("unstaking once unlocked");
+        assert_eq!(paid_to(&transfers, ALICE).last(), Some(&1_000));
+        assert!(host.state().stake.get(&ALICE).is_none());
+    }
+
+    #[test]
+    fn early_exits_reduce_the_rate_until_a_position_is_held_past_the_window() {
+        let (mut host, transfers) = setup(InitParams {
+            early_exit_decay: Some(EarlyExitDecay {
+                window_seconds:   24 * 60 * 60,
+                penalty_bps:      2_500,
+                recovery_seconds: 10 * *SECOND_PER_YEAR,
+            }),
+            ..init_params()
+        });
+        fund(&mut host, 10_000);
+        for exit in 0..2 {
+            let staked_at = START + exit * 2_000;
+            stake(&mut host, ALICE, 1_000, staked_at).expect("staking");
+            unstake(&mut host, ALICE, 1_000, staked_at + 1_000).expect("exiting early");
+        }
+
+        let staked_at = START + 4_000;
+        stake(&mut host, ALICE, 1_000, staked_at).expect("staking after two early exits");
+        assert_eq!(pending_reward(&host, ALICE, staked_at + YEAR_MILLIS), 500);
+
+        // Held past the window, so the exit forgives both early exits.
+        unstake(&mut host, ALICE, 1_000, staked_at + YEAR_MILLIS).expect("unstaking");
+        let staked_at = staked_at + YEAR_MILLIS;
+        stake(&mut host, ALICE, 1_000, staked_at).expect("staking again");
+        assert_eq!(pending_reward(&host, ALICE, staked_at + YEAR_MILLIS), 1_000);
+        // Two early principals back, then the reduced reward and its principal.
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000, 1_000, 500, 1_000]);
+    }
+
+    #[test]
+    fn funded_pool_drained_below_a_pending_reward_pays_what_it_holds() {
+        let (mut host, transfers) = setup(init_params());
+        fund(&mut host, 1_500);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+        assert_eq!(host.state().reward_pool, 500);
+
+        claim(&mut host, ALICE, START + 2 * YEAR_MILLIS).expect("claiming from the drained pool");
+
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000, 500]);
+        assert_eq!(host.state().reward_pool, 0);
+        assert_eq!(host.state().stake.get(&ALICE).map(|owner_state| owner_state.accrued), Some(500));
+        fund(&mut host, 500);
+        claim(&mut host, ALICE, START + 2 * YEAR_MILLIS).expect("claiming after refunding");
+        assert_eq!(paid_to(&transfers, ALICE), vec![1_000, 500, 500]);
+    }
+
+    #[test]
+    fn claim_moves_last_claimed_at_but_not_staked_start_at() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let claimed_at = START + YEAR_MILLIS / 2;
+
+        claim(&mut host, ALICE, claimed_at).expect("claiming");
+
+        let parameter = to_bytes(&ALICE);
+        let info = get_stake_info(&receive_ctx(ALICE, claimed_at, &parameter), &host).expect("viewing the stake");
+        assert_eq!(info.staked_start_at, START);
+        assert_eq!(info.last_claimed_at, claimed_at);
+    }
+
+    #[test]
+    fn second_stake_preserves_the_reward_of_the_first_interval() {
+        let (mut host, _) = setup(init_params());
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let first_interval = pending_reward(&host, ALICE, START + YEAR_MILLIS / 2);
+
+        stake(&mut host, ALICE, 3_000, START + YEAR_MILLIS / 2).expect("staking again");
+
+        assert_eq!(first_interval, 500);
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS / 2), 500);
+        assert_eq!(pending_reward(&host, ALICE, START + YEAR_MILLIS), 500 + 2_000);
+    }
+
+    #[test]
+    fn reward_transfer_is_sent_from_the_contract_not_its_owner() {
+        let (mut host, _) = setup(init_params());
+        fund(&mut host, 10_000);
+        stake(&mut host, ALICE, 1_000, START).expect("staking");
+        let senders = Rc::new(RefCell::new(Vec::new()));
+        let recorded = senders.clone();
+        host.setup_mock_entrypoint(
+            TOKEN,
+            OwnedEntrypointName::new_unchecked(TRANSFER_ENTRYPOINT_NAME.into()),
+            MockFn::new_v0::<(), _>(move |parameter, _, _, _| {
+                let params: TransferParameter =
+                    from_bytes(parameter.as_ref()).map_err(|_| CallContractError::Trap)?;
+                recorded.borrow_mut().extend(params.0.iter().map(|transfer| transfer.from));
+                Ok(false)
+            }),
+        );
+
+        claim(&mut host, ALICE, START + YEAR_MILLIS).expect("claiming");
+
+        // `receive_ctx` makes ADMIN the instance owner.
+        assert_eq!(*senders.borrow(), vec![Address::Contract(SELF_ADDRESS)]);
+    }
+}